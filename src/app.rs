@@ -1,317 +1,1780 @@
+use crate::conversion::{
+    ConversionMode, Hemisphere, LatitudeBand, Point, mgrs, parse_number_rows, utm_reference,
+};
+use crate::detection::{AxisOrder, Interpretation, detect};
+use crate::error::Error;
+use crate::export::Export;
+use crate::history::{History, Jump};
+use crate::labels::LabelPlacer;
+use crate::layer::{Appearance, Geometry, LAYER_COLORS, Layer, PointLabel, Side, paint_cluster};
+use crate::measure::{MeasureSource, MeasureTool, Measurement};
+use crate::overlays::Overlays;
+use crate::project::{ProjectFile, autosave_path};
+use crate::search::{
+    Gazetteer, Geocoder, Nominatim, Place, SearchAction, SearchBox, parse_coordinate,
+};
+use crate::settings::Settings;
+use crate::status::StatusLog;
+use crate::tile_cache::{
+    CachedTiles, MAX_PREFETCH_TILES, Prefetch, mbtiles_metadata, tiles_in_extent, trim_disk_cache,
+};
+use crate::tiles::{BaseMap, fit_view};
+use crate::validation::{ValidationTool, parse_table};
 use clipboard_win::{formats, get_clipboard, set_clipboard};
-use eframe::egui::{Button, ComboBox, DragValue, Grid};
+use eframe::egui::{Button, ComboBox, DragValue, Grid, TextEdit};
 use eframe::{App, egui};
-use egui::{Color32, Stroke};
+use egui::{Align2, Color32, FontId, Key, Label, Modifiers, Pos2, Rect, RichText, Sense, vec2};
 use egui_extras::{Column, TableBuilder};
-use itertools::{Itertools, izip};
-use regex::Regex;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
-use std::fs::File;
-use std::io::Write;
+use std::fs::{self, File};
 use std::path::PathBuf;
-use utm;
-use walkers::{HttpTiles, Map, MapMemory, Position, Projector, lon_lat, sources::OpenStreetMap};
-
-const DEFAULT_LAT: f64 = 41.651285;
-const DEFAULT_LON: f64 = -0.869147;
-
-// https://wiki.openstreetmap.org/wiki/Zoom_levels
-const TILE_WIDTHS: [f64; 21] = [
-    360.0, 180.0, 90.0, 45.0, 22.5, 11.25, 5.625, 2.813, 1.406, 0.703, 0.352, 0.176, 0.088, 0.044,
-    0.022, 0.011, 0.005, 0.003, 0.001, 0.0005, 0.00025,
-];
-
-fn parse_number_pairs(input: &str) -> Vec<[f32; 2]> {
-    let re = Regex::new(r"([+-]?\d+([.,]\d+)?([eE][+-]?\d+)?)").unwrap();
-
-    let numbers: Vec<f32> = re
-        .find_iter(input)
-        .filter_map(|m| m.as_str().replace(',', ".").parse::<f32>().ok())
-        .collect();
-
-    numbers
-        .chunks_exact(2)
-        .map(|chunk| [chunk[0], chunk[1]])
-        .collect()
-}
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use walkers::{Map, MapMemory, Position, Projector, Tiles, lon_lat};
+
+const SETTINGS_KEY: &str = "settings";
+const PREVIOUS_REGION_KEY: &str = "previous_region";
+const RECENT_FILES_KEY: &str = "recent_files";
+const EXCLUDE_INVALID_KEY: &str = "exclude_invalid";
+const EDIT_ON_MAP_KEY: &str = "edit_on_map";
+const OVERLAYS_KEY: &str = "overlays";
+
+const MAX_RECENT_FILES: usize = 8;
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+/// Layers with at least this many points are converted in the background.
+const BACKGROUND_POINTS: usize = 20_000;
+/// Markers of a layer in view above which they are grouped in clusters.
+const CLUSTER_POINTS: usize = 500;
+/// Side of the screen cells markers are clustered in, in points.
+const CLUSTER_CELL: f32 = 48.0;
+
+/// Point errors listed in the conversion report, the rest are counted.
+const REPORTED_ERRORS: usize = 3;
+
+/// Margin around the points when the map is fitted to them, in points.
+const FIT_PADDING: f32 = 40.0;
+/// Closest zoom the map is fitted at, for a single point.
+const FIT_MAX_ZOOM: f64 = 17.0;
 
 #[derive(PartialEq, Debug, Clone)]
-enum ConversionMode {
-    UTMtoLatLon,
-    LatLontoUTM,
+enum RowFilter {
+    All,
+    Valid,
+    Invalid,
 }
 
-impl fmt::Display for ConversionMode {
+impl fmt::Display for RowFilter {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ConversionMode::UTMtoLatLon => write!(f, "UTM to Lat/Lon"),
-            ConversionMode::LatLontoUTM => write!(f, "Lat/Lon to UTM"),
+            RowFilter::All => write!(f, "All points"),
+            RowFilter::Valid => write!(f, "Valid points"),
+            RowFilter::Invalid => write!(f, "Invalid points"),
         }
     }
 }
 
 #[derive(PartialEq, Debug, Clone)]
-enum Hemisphere {
-    North,
-    South,
+enum ExportScope {
+    ActiveLayer,
+    AllLayers,
 }
 
-impl fmt::Display for Hemisphere {
+impl fmt::Display for ExportScope {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Hemisphere::North => write!(f, "North"),
-            Hemisphere::South => write!(f, "South"),
+            ExportScope::ActiveLayer => write!(f, "Active layer"),
+            ExportScope::AllLayers => write!(f, "All layers"),
         }
     }
 }
 
-struct ConversionError;
+/// `lon` moved by whole turns to within 180° of `center_lon`.
+fn wrap_lon(lon: f64, center_lon: f64) -> f64 {
+    lon + 360.0 * ((center_lon - lon) / 360.0).round()
+}
+
+/// Screen position of [lon, lat] on the side of the antimeridian nearest the
+/// map center, so that data across it is drawn in one piece.
+fn project_near(projector: &Projector, center_lon: f64, [lon, lat]: [f64; 2]) -> Pos2 {
+    projector
+        .project(Position::new(wrap_lon(lon, center_lon), lat))
+        .to_pos2()
+}
+
+/// Everything an undo step restores: the layers with their points and settings.
+#[derive(Clone)]
+struct Snapshot {
+    layers: Vec<Layer>,
+    active: usize,
+}
+
+/// A large layer being converted on a worker thread.
+struct ConversionJob {
+    /// [`Layer::id`], the layer may have moved meanwhile
+    layer_id: u64,
+    /// The layer as it was sent, to tell whether it changed meanwhile
+    input: Layer,
+    handle: JoinHandle<(Layer, Result<(), Error>)>,
+}
 
 pub struct CoordinatesSuite {
-    conversion_mode: ConversionMode,
-    coords_geo: Vec<[f32; 2]>,
-    coords_utm: Vec<[f32; 2]>,
-    utm_zone: u8,
-    hemisphere: Hemisphere,
-    tiles: HttpTiles,
+    layers: Vec<Layer>,
+    active: usize,
+    previous_region: Option<[f64; 2]>,
+    row_filter: RowFilter,
+    exclude_invalid: bool,
+    export_scope: ExportScope,
+    selected: BTreeSet<usize>,
+    selection_anchor: Option<usize>,
+    scroll_to_row: Option<usize>,
+    edit_on_map: bool,
+    overlays: Overlays,
+    measure: MeasureTool,
+    validation: ValidationTool,
+    dragged_point: Option<usize>,
+    /// Map position under the mouse
+    cursor: Option<Position>,
+    /// Where the map context menu was opened
+    map_menu_position: Option<Position>,
+    map_rect: Option<Rect>,
+    /// Layer, index, screen position and radius of the markers drawn in the
+    /// last frame, the ones that can be hovered
+    drawn_markers: Vec<(usize, usize, Pos2, f32)>,
+    /// The map is fitted to the points when it first has a size
+    fit_pending: bool,
+    search: SearchBox,
+    /// Last place gone to from the search box, marked on the map
+    search_result: Option<Place>,
+    status: StatusLog,
+    history: History<Snapshot>,
+    project_path: Option<PathBuf>,
+    recent_files: Vec<PathBuf>,
+    saved_revision: u64,
+    autosaved_revision: u64,
+    last_autosave: Instant,
+    recovery_file: Option<PathBuf>,
+    close_dialog_open: bool,
+    close_confirmed: bool,
+    title: String,
+    settings: Settings,
+    settings_open: bool,
+    base_map: BaseMap,
+    custom_tiles_open: bool,
+    /// At most one per layer
+    conversions: Vec<ConversionJob>,
+    /// Style window of the active layer
+    style_open: bool,
+    tiles: CachedTiles,
+    prefetch: Option<Prefetch>,
+    prefetch_open: bool,
+    prefetch_max_zoom: u8,
     map_memory: MapMemory,
 }
 
 impl CoordinatesSuite {
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        std::thread::spawn(trim_disk_cache);
+        let storage = cc.storage;
+        let settings: Settings = storage
+            .and_then(|storage| eframe::get_value(storage, SETTINGS_KEY))
+            .unwrap_or_default();
         let mut coords_suite = CoordinatesSuite {
-            conversion_mode: ConversionMode::LatLontoUTM,
-            coords_geo: vec![],
-            coords_utm: vec![],
-            utm_zone: 30,
-            hemisphere: Hemisphere::North,
-            tiles: HttpTiles::new(OpenStreetMap, cc.egui_ctx.clone()),
+            layers: vec![],
+            active: 0,
+            previous_region: storage
+                .and_then(|storage| eframe::get_value(storage, PREVIOUS_REGION_KEY)),
+            row_filter: RowFilter::All,
+            exclude_invalid: storage
+                .and_then(|storage| eframe::get_value(storage, EXCLUDE_INVALID_KEY))
+                .unwrap_or(false),
+            export_scope: ExportScope::ActiveLayer,
+            selected: BTreeSet::new(),
+            selection_anchor: None,
+            scroll_to_row: None,
+            measure: MeasureTool::default(),
+            validation: ValidationTool::default(),
+            overlays: storage
+                .and_then(|storage| eframe::get_value(storage, OVERLAYS_KEY))
+                .unwrap_or_default(),
+            edit_on_map: storage
+                .and_then(|storage| eframe::get_value(storage, EDIT_ON_MAP_KEY))
+                .unwrap_or(false),
+            dragged_point: None,
+            cursor: None,
+            map_menu_position: None,
+            map_rect: None,
+            drawn_markers: vec![],
+            fit_pending: false,
+            search: SearchBox::new(Arc::new(Gazetteer::bundled())),
+            search_result: None,
+            status: StatusLog::default(),
+            history: History::default(),
+            project_path: None,
+            recent_files: storage
+                .and_then(|storage| eframe::get_value(storage, RECENT_FILES_KEY))
+                .unwrap_or_default(),
+            saved_revision: 0,
+            autosaved_revision: 0,
+            last_autosave: Instant::now(),
+            recovery_file: autosave_path().filter(|path| path.exists()),
+            close_dialog_open: false,
+            close_confirmed: false,
+            title: String::new(),
+            tiles: settings.base_map.tiles(&cc.egui_ctx),
+            base_map: settings.base_map.clone(),
+            custom_tiles_open: false,
+            conversions: vec![],
+            style_open: false,
+            prefetch: None,
+            prefetch_open: false,
+            prefetch_max_zoom: 16,
+            settings,
+            settings_open: false,
             map_memory: MapMemory::default(),
         };
+        coords_suite.layers = vec![coords_suite.new_layer(0)];
+        if coords_suite.settings.gazetteer.is_some() {
+            let gazetteer = coords_suite.load_gazetteer();
+            coords_suite.search.set_gazetteer(gazetteer);
+        }
 
         coords_suite.parse_coordinates();
         coords_suite.move_map_to_points();
         coords_suite
     }
 
-    fn compute_geo_coords(&mut self) -> Result<(), ConversionError> {
-        if self.coords_utm.is_empty() {
-            return Err(ConversionError);
+    fn layer(&self) -> &Layer {
+        &self.layers[self.active]
+    }
+
+    fn layer_mut(&mut self) -> &mut Layer {
+        &mut self.layers[self.active]
+    }
+
+    fn set_active_layer(&mut self, index: usize) {
+        if index != self.active {
+            self.active = index;
+            self.selected.clear();
+            self.selection_anchor = None;
+        }
+    }
+
+    /// An empty layer with the defaults of the settings.
+    fn new_layer(&self, index: usize) -> Layer {
+        let mut layer = Layer::new(
+            format!("Layer {}", index + 1),
+            LAYER_COLORS[index % LAYER_COLORS.len()],
+        );
+        layer.marker = self.settings.marker_style;
+        layer.marker_size = self.settings.marker_size;
+        layer.utm_zone = self.settings.default_zone;
+        layer.hemisphere = self.settings.default_hemisphere.clone();
+        layer
+    }
+
+    fn add_layer(&mut self) {
+        self.record("New layer");
+        let n_layers = self.layers.len();
+        self.layers.push(self.new_layer(n_layers));
+        self.set_active_layer(n_layers);
+    }
+
+    fn remove_active_layer(&mut self) {
+        if self.layers.len() == 1 {
+            return;
+        }
+        self.record(format!("Delete layer {}", self.layer().name));
+        self.layers.remove(self.active);
+        self.active = self.active.min(self.layers.len() - 1);
+        self.selected.clear();
+        self.selection_anchor = None;
+    }
+
+    fn is_modified(&self) -> bool {
+        self.history.revision() != self.saved_revision
+    }
+
+    fn project_file(&self) -> ProjectFile {
+        let center = self.map_memory.detached().unwrap_or(self.home());
+        ProjectFile::new(
+            &self.layers,
+            self.active,
+            [center.x(), center.y()],
+            self.map_memory.zoom(),
+            self.base_map.clone(),
+        )
+    }
+
+    fn load_project(&mut self, ctx: &egui::Context, project: ProjectFile) {
+        let (layers, active, [lon, lat], zoom, base_map) = project.into_parts();
+        self.set_base_map(ctx, base_map.unwrap_or(self.settings.base_map.clone()));
+        self.layers = layers;
+        self.active = active;
+        for l in 0..self.layers.len() {
+            if self.layers[l].points.len() >= BACKGROUND_POINTS {
+                self.convert_in_background(l);
+            } else {
+                // an empty layer has nothing to convert
+                let _ = self.layers[l].convert();
+            }
+        }
+        self.selected.clear();
+        self.selection_anchor = None;
+        self.dragged_point = None;
+        self.map_memory.center_at(lon_lat(lon, lat));
+        let _ = self.map_memory.set_zoom(zoom);
+    }
+
+    fn new_project(&mut self, ctx: &egui::Context) {
+        self.record("New project");
+        self.set_base_map(ctx, self.settings.base_map.clone());
+        self.layers = vec![self.new_layer(0)];
+        self.active = 0;
+        self.selected.clear();
+        self.selection_anchor = None;
+        self.project_path = None;
+        self.saved_revision = self.history.revision();
+    }
+
+    fn open_project(&mut self, ctx: &egui::Context, path: PathBuf) {
+        match ProjectFile::load(&path) {
+            Ok(project) => {
+                self.record(format!("Open {}", path.display()));
+                self.load_project(ctx, project);
+                self.saved_revision = self.history.revision();
+                self.status.info(format!("Opened {}", path.display()));
+                self.add_recent_file(path.clone());
+                self.project_path = Some(path);
+            }
+            Err(e) => self.status.error(e),
+        }
+    }
+
+    fn open_dialog(&mut self, ctx: &egui::Context) {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter("Projects", &["json"])
+            .pick_file()
+        {
+            self.open_project(ctx, path);
+        }
+    }
+
+    /// Returns whether the project was saved.
+    fn save_project(&mut self, path: PathBuf) -> bool {
+        match self.project_file().save(&path) {
+            Ok(()) => {
+                self.saved_revision = self.history.revision();
+                self.status.info(format!("Saved to {}", path.display()));
+                self.add_recent_file(path.clone());
+                self.project_path = Some(path);
+                self.remove_autosave();
+                true
+            }
+            Err(e) => {
+                self.status.error(e);
+                false
+            }
+        }
+    }
+
+    fn save(&mut self) -> bool {
+        match self.project_path.clone() {
+            Some(path) => self.save_project(path),
+            None => self.save_as(),
+        }
+    }
+
+    fn save_as(&mut self) -> bool {
+        match rfd::FileDialog::new()
+            .add_filter("Projects", &["json"])
+            .set_file_name("project.json")
+            .save_file()
+        {
+            Some(path) => self.save_project(path),
+            None => false,
+        }
+    }
+
+    fn add_recent_file(&mut self, path: PathBuf) {
+        self.recent_files.retain(|recent| *recent != path);
+        self.recent_files.insert(0, path);
+        self.recent_files.truncate(MAX_RECENT_FILES);
+    }
+
+    /// Writes unsaved changes to the autosave file every `AUTOSAVE_INTERVAL`.
+    fn autosave(&mut self, ctx: &egui::Context) {
+        // the file may still be recovered
+        if self.recovery_file.is_some() {
+            return;
+        }
+        let revision = self.history.revision();
+        if !self.is_modified() || revision == self.autosaved_revision {
+            return;
+        }
+        let elapsed = self.last_autosave.elapsed();
+        if elapsed < AUTOSAVE_INTERVAL {
+            // come back even if nothing else triggers a repaint
+            ctx.request_repaint_after(AUTOSAVE_INTERVAL - elapsed);
+            return;
+        }
+        self.last_autosave = Instant::now();
+        self.autosaved_revision = revision;
+
+        let Some(path) = autosave_path() else { return };
+        let result = match path.parent() {
+            Some(dir) => fs::create_dir_all(dir).map_err(Error::from),
+            None => Ok(()),
+        }
+        .and_then(|()| self.project_file().save(&path));
+        if let Err(e) = result {
+            self.status.error(e);
         }
+    }
+
+    fn remove_autosave(&mut self) {
+        if let Some(path) = autosave_path() {
+            let _ = fs::remove_file(path);
+        }
+        self.autosaved_revision = self.history.revision();
+    }
+
+    fn recover_autosave(&mut self, ctx: &egui::Context, path: PathBuf) {
+        match ProjectFile::load(&path) {
+            Ok(project) => {
+                self.record("Recover unsaved session");
+                self.load_project(ctx, project);
+                self.status.info("Recovered the unsaved session");
+            }
+            Err(e) => self.status.error(e),
+        }
+    }
+
+    fn show_menu_bar(&mut self, ui: &mut egui::Ui) {
+        egui::menu::bar(ui, |ui| {
+            ui.menu_button("File", |ui| {
+                if ui.button("New project").clicked() {
+                    self.new_project(ui.ctx());
+                    ui.close_menu();
+                }
+                if ui.button("Open...  (Ctrl+O)").clicked() {
+                    ui.close_menu();
+                    self.open_dialog(ui.ctx());
+                }
+                ui.menu_button("Open recent", |ui| {
+                    if self.recent_files.is_empty() {
+                        ui.label("No recent files");
+                    }
+                    let mut opened = None;
+                    for path in &self.recent_files {
+                        if ui.button(path.display().to_string()).clicked() {
+                            opened = Some(path.clone());
+                        }
+                    }
+                    if let Some(path) = opened {
+                        ui.close_menu();
+                        self.open_project(ui.ctx(), path);
+                    }
+                });
+                ui.separator();
+                if ui.button("Save  (Ctrl+S)").clicked() {
+                    ui.close_menu();
+                    self.save();
+                }
+                if ui.button("Save as...  (Ctrl+Shift+S)").clicked() {
+                    ui.close_menu();
+                    self.save_as();
+                }
+                ui.separator();
+                if ui.button("Settings...").clicked() {
+                    ui.close_menu();
+                    self.settings_open = true;
+                }
+            });
+        });
+    }
 
-        let zone_letter = match self.hemisphere {
-            Hemisphere::North => 'N',
-            Hemisphere::South => 'J', // the utm function only checks if letter >= 'N'
+    fn home(&self) -> Position {
+        let [lon, lat] = self.settings.home;
+        lon_lat(lon, lat)
+    }
+
+    /// File dialog for exports, opened in the export folder of the settings.
+    fn export_dialog(&self) -> rfd::FileDialog {
+        match &self.settings.export_dir {
+            Some(dir) => rfd::FileDialog::new().set_directory(dir),
+            None => rfd::FileDialog::new(),
+        }
+    }
+
+    fn set_base_map(&mut self, ctx: &egui::Context, base_map: BaseMap) {
+        if base_map != self.base_map {
+            self.tiles = base_map.tiles(ctx);
+            self.base_map = base_map;
+        }
+    }
+
+    fn pick_mbtiles(&mut self) -> Option<BaseMap> {
+        let path = rfd::FileDialog::new()
+            .add_filter("MBTiles", &["mbtiles"])
+            .pick_file()?;
+        match mbtiles_metadata(&path) {
+            Ok(_) => Some(BaseMap::MbTiles(path)),
+            Err(e) => {
+                self.status.error(e);
+                None
+            }
+        }
+    }
+
+    /// Tiles in view, from the current zoom to `prefetch_max_zoom`.
+    fn prefetch_tiles(&self) -> Vec<walkers::TileId> {
+        let Some(rect) = self.map_rect else {
+            return vec![];
         };
+        let projector = Projector::new(rect, &self.map_memory, self.home());
+        let zoom = self.map_memory.zoom().floor() as u8;
+        tiles_in_extent(
+            projector.unproject(rect.left_top().to_vec2()),
+            projector.unproject(rect.right_bottom().to_vec2()),
+            zoom..=self.prefetch_max_zoom.max(zoom),
+        )
+    }
 
-        self.coords_geo = self
-            .coords_utm
-            .iter()
-            .map(|&[x, y]| {
-                match utm::wsg84_utm_to_lat_lon(x.into(), y.into(), self.utm_zone, zone_letter) {
-                    Ok((lat, lon)) => Ok([lon as f32, lat as f32]),
-                    Err(_) => Err(ConversionError),
+    fn show_prefetch_window(&mut self, ctx: &egui::Context) {
+        if let Some(prefetch) = &self.prefetch
+            && prefetch.is_finished()
+        {
+            self.status.info(format!(
+                "Prefetched {} of {} tiles, {} not available",
+                prefetch.done(),
+                prefetch.total,
+                prefetch.failed()
+            ));
+            self.prefetch = None;
+        }
+        let mut open = self.prefetch_open;
+        egui::Window::new("Prefetch tiles")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                if let Some(prefetch) = &self.prefetch {
+                    ui.add(
+                        egui::ProgressBar::new(prefetch.done() as f32 / prefetch.total as f32)
+                            .text(format!("{} of {} tiles", prefetch.done(), prefetch.total)),
+                    );
+                    if ui.button("Cancel").clicked() {
+                        prefetch.cancel();
+                    }
+                    ctx.request_repaint_after(Duration::from_millis(200));
+                    return;
+                }
+                if self.base_map == BaseMap::OpenStreetMap {
+                    ui.label(
+                        "The OpenStreetMap tile servers don't allow bulk downloads, \
+                         choose another base map to prefetch.",
+                    );
+                    return;
+                }
+                if !self.tiles.can_prefetch() {
+                    ui.label("Only tiles from a tile server are cached, this base map is already on disk.");
+                    return;
                 }
+                ui.label("Downloads the tiles of the area in view to use the map offline.");
+                let zoom = self.map_memory.zoom().floor() as u8;
+                ui.horizontal(|ui| {
+                    ui.label(format!("Zoom levels {} to", zoom));
+                    ui.add(
+                        DragValue::new(&mut self.prefetch_max_zoom)
+                            .range(zoom.min(self.tiles.max_zoom())..=self.tiles.max_zoom()),
+                    );
+                });
+                let tile_ids = self.prefetch_tiles();
+                if tile_ids.len() > MAX_PREFETCH_TILES {
+                    ui.colored_label(
+                        Color32::RED,
+                        format!(
+                            "More than {} tiles, zoom in or lower the zoom levels",
+                            MAX_PREFETCH_TILES
+                        ),
+                    );
+                } else {
+                    ui.label(format!("{} tiles", tile_ids.len()));
+                    if ui
+                        .add_enabled(!tile_ids.is_empty(), Button::new("Start"))
+                        .clicked()
+                    {
+                        self.prefetch = Some(self.tiles.prefetch(tile_ids));
+                    }
+                }
+            });
+        self.prefetch_open = open;
+    }
+
+    fn show_custom_tiles_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.custom_tiles_open;
+        let mut apply = false;
+        egui::Window::new("Custom tile service")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                self.settings.custom_tiles.show(ui);
+                ui.separator();
+                apply = ui
+                    .add_enabled(
+                        !self.settings.custom_tiles.url.is_empty(),
+                        Button::new("Use as base map"),
+                    )
+                    .clicked();
+            });
+        if apply {
+            self.set_base_map(ctx, BaseMap::Custom(self.settings.custom_tiles.clone()));
+            open = false;
+        }
+        self.custom_tiles_open = open;
+    }
+
+    fn show_style_window(&mut self, ctx: &egui::Context) {
+        let mut open = self.style_open;
+        egui::Window::new(format!("Style of {}", self.layer().name))
+            .id(egui::Id::new("layer_style_window"))
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let before = self.appearances();
+                self.layers[self.active].show_style(ui, &self.settings);
+                self.record_appearances(before);
+            });
+        self.style_open = open;
+    }
+
+    fn show_settings_window(&mut self, ctx: &egui::Context) {
+        let map_center = self
+            .map_memory
+            .detached()
+            .map(|center| [center.x(), center.y()]);
+        let gazetteer = self.settings.gazetteer.clone();
+        egui::Window::new("Settings")
+            .open(&mut self.settings_open)
+            .resizable(false)
+            .show(ctx, |ui| self.settings.show(ui, map_center));
+        if self.settings.gazetteer != gazetteer {
+            let gazetteer = self.load_gazetteer();
+            self.search.set_gazetteer(gazetteer);
+        }
+    }
+
+    /// The place names of the settings, the bundled ones if they cannot be read.
+    fn load_gazetteer(&mut self) -> Arc<dyn Geocoder> {
+        let Some(path) = &self.settings.gazetteer else {
+            return Arc::new(Gazetteer::bundled());
+        };
+        match Gazetteer::from_file(path) {
+            Ok(gazetteer) => Arc::new(gazetteer),
+            Err(e) => {
+                self.status.error(e);
+                Arc::new(Gazetteer::bundled())
+            }
+        }
+    }
+
+    /// The search box at the top left corner of the map.
+    fn show_search(&mut self, ctx: &egui::Context, map_rect: Rect) {
+        let layer = self.layer();
+        let (zone, hemisphere) = (layer.utm_zone, layer.hemisphere.clone());
+        let previous_region = self.previous_region;
+        let online = (!self.settings.geocoder_url.trim().is_empty()).then(|| {
+            Arc::new(Nominatim {
+                url: self.settings.geocoder_url.clone(),
+            }) as Arc<dyn Geocoder>
+        });
+        let action = egui::Area::new(egui::Id::new("map_search"))
+            .fixed_pos(map_rect.left_top() + vec2(10.0, 10.0))
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style())
+                    .show(ui, |ui| {
+                        self.search.show(
+                            ui,
+                            |query| parse_coordinate(query, zone, &hemisphere, previous_region),
+                            online,
+                        )
+                    })
+                    .inner
             })
-            .collect::<Result<Vec<_>, _>>()?;
+            .inner;
+        if let Some(e) = self.search.error.take() {
+            self.status.error(e);
+        }
+        let place = match action {
+            Some(SearchAction::Go(place)) => place,
+            Some(SearchAction::Add(place)) => {
+                let [lon, lat] = place.position;
+                self.add_point_at(Position::new(lon, lat));
+                self.status
+                    .info(format!("Added {} to {}", place.name, self.layer().name));
+                place
+            }
+            None => return,
+        };
+        let [lon, lat] = place.position;
+        self.map_memory.center_at(Position::new(lon, lat));
+        let zoom: f64 = if place.is_coordinate { 16.0 } else { 12.0 };
+        let _ = self
+            .map_memory
+            .set_zoom(zoom.min(self.tiles.max_zoom() as f64));
+        self.search_result = Some(place);
+    }
 
-        Ok(())
+    /// Asks before closing the window with unsaved changes.
+    fn show_close_dialog(&mut self, ctx: &egui::Context) {
+        if ctx.input(|input| input.viewport().close_requested())
+            && self.is_modified()
+            && !self.close_confirmed
+        {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.close_dialog_open = true;
+        }
+        if !self.close_dialog_open {
+            return;
+        }
+
+        egui::Window::new("Unsaved changes")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label("Save the changes to the project before closing?");
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        self.close_dialog_open = false;
+                        self.close_confirmed = self.save();
+                    }
+                    if ui.button("Don't save").clicked() {
+                        self.close_dialog_open = false;
+                        self.close_confirmed = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        self.close_dialog_open = false;
+                    }
+                });
+            });
+        if self.close_confirmed {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+    }
+
+    fn show_recovery_dialog(&mut self, ctx: &egui::Context) {
+        let Some(path) = self.recovery_file.clone() else {
+            return;
+        };
+        egui::Window::new("Recover unsaved session")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label("The program did not close properly last time.");
+                ui.label("Restore the unsaved changes of that session?");
+                ui.horizontal(|ui| {
+                    if ui.button("Restore").clicked() {
+                        self.recovery_file = None;
+                        self.recover_autosave(ctx, path);
+                    }
+                    if ui.button("Discard").clicked() {
+                        self.recovery_file = None;
+                        self.remove_autosave();
+                    }
+                });
+            });
     }
 
-    fn compute_utm_coords(&mut self) -> Result<(), ConversionError> {
-        if self.coords_geo.is_empty() {
-            return Err(ConversionError);
+    fn update_title(&mut self, ctx: &egui::Context) {
+        let name = match &self.project_path {
+            Some(path) => path
+                .file_name()
+                .map_or(String::new(), |name| name.to_string_lossy().to_string()),
+            None => "Untitled".to_string(),
+        };
+        let modified = if self.is_modified() { "*" } else { "" };
+        let title = format!("{}{} - {}", name, modified, crate::APP_ID);
+        if title != self.title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.title = title;
         }
+    }
 
-        let [lon, lat] = self.coords_geo[0];
-        self.utm_zone = utm::lat_lon_to_zone_number(lat.into(), lon.into());
-        self.hemisphere = if lat >= 0.0 {
-            Hemisphere::North
-        } else {
-            Hemisphere::South
+    fn convert(&mut self) {
+        if self.layer().points.len() >= BACKGROUND_POINTS {
+            self.convert_in_background(self.active);
+            return;
+        }
+        if let Err(e) = self.layer_mut().convert() {
+            self.status.error(e);
+            return;
+        }
+        self.report_conversion(self.active);
+    }
+
+    /// Converts a large layer on a worker thread, its points are replaced
+    /// when it is done. A conversion of the same layer still running is
+    /// superseded, those of other layers go on.
+    fn convert_in_background(&mut self, l: usize) {
+        let input = self.layers[l].clone();
+        self.conversions.retain(|job| job.layer_id != input.id());
+        let mut output = input.clone();
+        let handle = std::thread::spawn(move || {
+            let result = output.convert();
+            (output, result)
+        });
+        self.status.info(format!(
+            "{}: converting {} points...",
+            input.name,
+            input.points.len()
+        ));
+        self.conversions.push(ConversionJob {
+            layer_id: input.id(),
+            input,
+            handle,
+        });
+    }
+
+    fn is_converting(&self, l: usize) -> bool {
+        let id = self.layers[l].id();
+        self.conversions.iter().any(|job| job.layer_id == id)
+    }
+
+    fn poll_conversions(&mut self) {
+        let (finished, running) = std::mem::take(&mut self.conversions)
+            .into_iter()
+            .partition(|job| job.handle.is_finished());
+        self.conversions = running;
+        for job in finished {
+            self.finish_conversion(job);
+        }
+    }
+
+    fn finish_conversion(&mut self, job: ConversionJob) {
+        let Ok((output, result)) = job.handle.join() else {
+            self.status.error("The conversion stopped unexpectedly");
+            return;
+        };
+        // the layer was removed meanwhile
+        let Some(l) = self
+            .layers
+            .iter()
+            .position(|layer| layer.id() == job.layer_id)
+        else {
+            return;
         };
+        let layer = &mut self.layers[l];
+        // edited or undone while it ran
+        if !layer.same_conversion_input(&job.input) {
+            self.convert_in_background(l);
+            return;
+        }
+        if let Err(e) = result {
+            self.status.error(e);
+            return;
+        }
+        layer.points = output.points;
+        layer.utm_zone = output.utm_zone;
+        layer.hemisphere = output.hemisphere;
+        self.validation.invalidate();
+        // UTM input had nothing to show on the map until now
+        if l == self.active && job.input.valid_geo_coords().next().is_none() {
+            self.move_map_to_points();
+        }
+        self.update_previous_region(l);
+        self.report_conversion(l);
+    }
 
-        self.coords_utm = self
-            .coords_geo
+    fn report_conversion(&mut self, l: usize) {
+        let layer = &self.layers[l];
+        let errors: Vec<Error> = layer
+            .points
             .iter()
-            .map(|&[lon, lat]| utm::to_utm_wgs84_no_zone(lat.into(), lon.into()))
-            .map(|(y, x, _mc)| [x as f32, y as f32])
+            .filter_map(|p| p.error.clone())
             .collect();
-
-        Ok(())
+        let summary = format!(
+            "{}: {} of {} points converted ({})",
+            layer.name,
+            layer.points.len() - errors.len(),
+            layer.points.len(),
+            layer.conversion_mode
+        );
+        // one notification, not one per point of a large layer
+        if !errors.is_empty() {
+            let mut message = errors
+                .iter()
+                .take(REPORTED_ERRORS)
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join("; ");
+            if errors.len() > REPORTED_ERRORS {
+                message += &format!(" and {} more", errors.len() - REPORTED_ERRORS);
+            }
+            self.status.error(format!(
+                "{}: {} points not converted: {}",
+                layer.name,
+                errors.len(),
+                message
+            ));
+        }
+        self.status.info(summary);
     }
 
-    fn parse_coordinates(&mut self) {
+    /// Loads the coordinates in the clipboard into the active layer, returns
+    /// whether there were any.
+    fn parse_coordinates(&mut self) -> bool {
         let clipboard_content = match get_clipboard(formats::Unicode) {
             Ok(content) => content,
             Err(e) => {
-                println!("{:?}", e);
+                self.status.error(Error::Clipboard(e.to_string()));
                 "".to_string()
             }
         };
 
-        let coords = parse_number_pairs(&clipboard_content);
+        let (rows, parse_errors) = parse_number_rows(&clipboard_content);
+        for e in parse_errors {
+            self.status.error(e);
+        }
+
+        if rows.is_empty() {
+            self.status.info("No coordinates found in the clipboard");
+            return false;
+        }
+
+        let detection = detect(&rows, self.previous_region);
+        if detection.is_recognised() {
+            self.status.info(format!(
+                "Detected {} ({:.0}% confidence)",
+                detection.best(),
+                100.0 * detection.confidence()
+            ));
+        } else {
+            self.status
+                .error("Coordinate format not recognised, the values fit no supported system");
+        }
+        let layer = self.layer_mut();
+        let interpretation = detection.best().with_axis_order(layer.axis_order);
+        layer.detection = Some(detection);
+        layer.raw_rows = rows;
+        self.selected.clear();
+        self.apply_interpretation(interpretation);
+        true
+    }
+
+    /// The only place the clipboard is read after startup, settings changes
+    /// work on the rows that are already loaded.
+    fn reload_clipboard(&mut self) {
+        let before = self.snapshot();
+        if self.parse_coordinates() {
+            self.history.record("Read from clipboard", before);
+            self.move_map_to_points();
+        }
+    }
 
-        if coords.is_empty() {
+    /// Converts the loaded points again, after a zone or hemisphere change.
+    fn reconvert(&mut self) {
+        if !self.layer().points.is_empty() {
+            self.convert();
+        }
+    }
+
+    /// Reads the loaded rows as the input of `mode`, in the best detected
+    /// interpretation of that kind.
+    fn set_conversion_mode(&mut self, mode: ConversionMode) {
+        let layer = self.layer_mut();
+        layer.conversion_mode = mode;
+        if layer.raw_rows.is_empty() {
             return;
         }
+        let geographic = layer.conversion_mode == ConversionMode::LatLontoUTM;
+        let detected = layer.detection.as_ref().and_then(|detection| {
+            detection
+                .candidates
+                .iter()
+                .map(|(interpretation, _score)| *interpretation)
+                .find(|interpretation| interpretation.is_geographic() == geographic)
+        });
+        let interpretation = match detected {
+            Some(interpretation) => interpretation,
+            None if geographic => Interpretation::LatLon,
+            None => Interpretation::EastingNorthing,
+        };
+        let axis_order = layer.axis_order;
+        self.apply_interpretation(interpretation.with_axis_order(axis_order));
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            layers: self.layers.clone(),
+            active: self.active,
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.layers = snapshot.layers;
+        self.active = snapshot.active;
+        let n_points = self.layer().points.len();
+        self.selected.retain(|&i| i < n_points);
+        self.selection_anchor = None;
+        self.dragged_point = None;
+    }
+
+    /// Saves the current state as the one to go back to when `label` is undone.
+    fn record(&mut self, label: impl Into<String>) {
+        let snapshot = self.snapshot();
+        self.history.record(label, snapshot);
+    }
+
+    fn appearances(&self) -> Vec<Appearance> {
+        self.layers.iter().map(Layer::appearance).collect()
+    }
 
-        self.conversion_mode = if coords[0][1] > 1000.0 {
-            ConversionMode::UTMtoLatLon
+    /// Records a change to the name, visibility or style of a layer, given
+    /// their `before` values. Continuous edits such as typing a name or
+    /// dragging a colour are undone in one step.
+    fn record_appearances(&mut self, before: Vec<Appearance>) {
+        let Some(l) = (0..self.layers.len()).find(|&l| self.layers[l].appearance() != before[l])
+        else {
+            return;
+        };
+        let (old, new) = (&before[l], self.layers[l].appearance());
+        let toggled = old.visible != new.visible;
+        let label = if old.name != new.name {
+            format!("Rename layer {}", l + 1)
+        } else if toggled {
+            let action = if new.visible { "Show" } else { "Hide" };
+            format!("{} {}", action, new.name)
         } else {
-            ConversionMode::LatLontoUTM
+            format!("Style of {}", new.name)
         };
+        let mut snapshot = self.snapshot();
+        for (layer, appearance) in snapshot.layers.iter_mut().zip(before) {
+            layer.set_appearance(appearance);
+        }
+        if toggled {
+            self.history.record(label, snapshot);
+        } else {
+            self.history.record_merged(label, snapshot);
+        }
+    }
 
-        match self.conversion_mode {
-            ConversionMode::UTMtoLatLon => {
-                self.coords_utm = coords;
-                match self.compute_geo_coords() {
-                    Ok(()) => println!("Conversion succesful"),
-                    Err(_) => println!("Conversion failed"),
-                };
+    fn undo(&mut self) {
+        let current = self.snapshot();
+        if let Some((label, snapshot)) = self.history.undo(current) {
+            self.restore(snapshot);
+            self.status.info(format!("Undone: {}", label));
+        }
+    }
+
+    fn redo(&mut self) {
+        let current = self.snapshot();
+        if let Some((label, snapshot)) = self.history.redo(current) {
+            self.restore(snapshot);
+            self.status.info(format!("Redone: {}", label));
+        }
+    }
+
+    fn select_row(&mut self, i: usize, modifiers: egui::Modifiers) {
+        match self.selection_anchor {
+            Some(anchor) if modifiers.shift => {
+                self.selected = (anchor.min(i)..=anchor.max(i)).collect();
             }
-            ConversionMode::LatLontoUTM => {
-                self.coords_geo = coords.iter().map(|&[lon, lat]| [lat, lon]).collect();
-                match self.compute_utm_coords() {
-                    Ok(()) => println!("Conversion succesful"),
-                    Err(_) => println!("Conversion failed"),
-                };
+            _ if modifiers.command => {
+                if !self.selected.remove(&i) {
+                    self.selected.insert(i);
+                }
+                self.selection_anchor = Some(i);
+            }
+            _ => {
+                self.selected = BTreeSet::from([i]);
+                self.selection_anchor = Some(i);
+            }
+        }
+    }
+
+    fn insert_point(&mut self) {
+        self.record("Insert point");
+        let selected = self.selected.last().copied();
+        let [lon, lat] = self.settings.home;
+        let layer = self.layer_mut();
+        let i = selected.map_or(layer.points.len(), |i| i + 1);
+        let point = match selected {
+            Some(selected) => layer.points[selected].clone(),
+            None => layer
+                .points
+                .last()
+                .cloned()
+                .unwrap_or_else(|| Point::from_geo(lon, lat, 0.0)),
+        };
+        layer.points.insert(i, point);
+        layer.reconvert_point(i, Side::Geo);
+        layer.renumber_errors();
+        layer.store_raw_rows();
+        self.selected = BTreeSet::from([i]);
+        self.selection_anchor = Some(i);
+    }
+
+    fn delete_selected(&mut self) {
+        if self.selected.is_empty() {
+            return;
+        }
+        self.record(format!("Delete {} points", self.selected.len()));
+        let selected = std::mem::take(&mut self.selected);
+        let layer = self.layer_mut();
+        for &i in selected.iter().rev() {
+            layer.points.remove(i);
+        }
+        layer.renumber_errors();
+        layer.store_raw_rows();
+        self.status
+            .info(format!("Deleted {} points", selected.len()));
+        self.selection_anchor = None;
+    }
+
+    fn delete_point(&mut self, i: usize) {
+        self.selected = BTreeSet::from([i]);
+        self.delete_selected();
+    }
+
+    fn add_point_at(&mut self, position: Position) {
+        self.record("Add point on map");
+        let layer = self.layer_mut();
+        let i = layer.points.len();
+        layer
+            .points
+            .push(Point::from_geo(position.x(), position.y(), 0.0));
+        layer.reconvert_point(i, Side::Geo);
+        layer.store_raw_rows();
+        self.selected = BTreeSet::from([i]);
+        self.selection_anchor = Some(i);
+        self.scroll_to_row = Some(i);
+    }
+
+    fn measured_vertices(&self) -> Vec<[f64; 2]> {
+        let layer = self.layer();
+        let geo = |i: usize| {
+            let point = &layer.points[i];
+            point.geo.filter(|_| point.is_valid())
+        };
+        match self.measure.source {
+            MeasureSource::Drawn => self.measure.vertices.clone(),
+            MeasureSource::Selection => self.selected.iter().filter_map(|&i| geo(i)).collect(),
+            MeasureSource::ActiveLayer => (0..layer.points.len()).filter_map(geo).collect(),
+        }
+    }
+
+    fn show_measure_window(&mut self, ctx: &egui::Context) {
+        let measurement = Measurement::new(&self.measured_vertices(), self.layer().utm_zone);
+        let mut open = self.measure.window_open;
+        egui::Window::new("Measure")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                self.measure.show_source(ui);
+                measurement.show(ui, &self.settings);
+            });
+        self.measure.window_open = open;
+    }
+
+    fn show_validation_window(&mut self, ctx: &egui::Context) {
+        if !self.validation.window_open {
+            return;
+        }
+        let report = self
+            .validation
+            .report(&self.layers[self.active], self.history.revision());
+        let mut open = self.validation.window_open;
+        egui::Window::new("Validate")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("Each point is converted {} and back.", report.mode));
+                self.validation.show_tolerance(ui);
+                ui.horizontal(|ui| {
+                    let expected = match report.mode {
+                        ConversionMode::LatLontoUTM => "easting and northing",
+                        ConversionMode::UTMtoLatLon => "latitude and longitude",
+                    };
+                    if ui
+                        .button("Paste reference")
+                        .on_hover_text(format!(
+                            "The expected {} of each point, from the clipboard",
+                            expected
+                        ))
+                        .clicked()
+                    {
+                        self.paste_reference();
+                    }
+                    if self.validation.table_rows() > 0 {
+                        ui.label(format!("{} reference rows", self.validation.table_rows()));
+                        if ui.small_button("Clear").clicked() {
+                            self.validation.set_table(vec![]);
+                        }
+                    }
+                });
+                if self.validation.table_rows() > 0 {
+                    ui.horizontal(|ui| self.validation.show_columns(ui));
+                }
+                ui.separator();
+                report.show(ui, &self.settings);
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(
+                            report.flagged().next().is_some(),
+                            Button::new("Select flagged"),
+                        )
+                        .clicked()
+                    {
+                        self.selected = report.flagged().map(|check| check.point).collect();
+                        self.selection_anchor = self.selected.first().copied();
+                        self.scroll_to_row = self.selection_anchor;
+                    }
+                    if ui.button("Export QA report...").clicked()
+                        && let Some(outfile) = self
+                            .export_dialog()
+                            .add_filter("CSV files", &["csv", "txt"])
+                            .set_file_name("qa_report.csv")
+                            .save_file()
+                    {
+                        match File::create(&outfile)
+                            .and_then(|mut file| report.write(&mut file, &self.settings))
+                        {
+                            Ok(()) => self
+                                .status
+                                .info(format!("Exported to {}", outfile.display())),
+                            Err(e) => self.status.error(Error::from(e)),
+                        }
+                    }
+                });
+            });
+        self.validation.window_open = open;
+    }
+
+    /// Reads the reference coordinates for the validation from the clipboard.
+    fn paste_reference(&mut self) {
+        let content: String = match get_clipboard(formats::Unicode) {
+            Ok(content) => content,
+            Err(e) => {
+                self.status.error(Error::Clipboard(e.to_string()));
+                return;
             }
+        };
+        let table = parse_table(&content);
+        if table.len() != self.layer().points.len() {
+            self.status.info(format!(
+                "{} reference rows for {} points, compared by row",
+                table.len(),
+                self.layer().points.len()
+            ));
         }
+        self.validation.set_table(table);
     }
 
-    fn calculate_zoom_level(&self) -> f64 {
-        if self.coords_geo.len() == 1 {
-            return 15.0;
+    /// The measured line with the length of each segment, closed with a dashed
+    /// segment when it also measures an area.
+    fn draw_measurement(&self, painter: &egui::Painter, projector: &Projector) {
+        let vertices = self.measured_vertices();
+        let center_lon = projector
+            .unproject(painter.clip_rect().center().to_vec2())
+            .x();
+        let screen: Vec<Pos2> = vertices
+            .iter()
+            .map(|&position| project_near(projector, center_lon, position))
+            .collect();
+        let color = Color32::from_rgb(255, 140, 0);
+        let stroke = egui::Stroke::new(2.0, color);
+        painter.add(egui::Shape::line(screen.clone(), stroke));
+        if let (Some(first), Some(last), true) = (screen.first(), screen.last(), screen.len() >= 3)
+        {
+            painter.extend(egui::Shape::dashed_line(&[*last, *first], stroke, 6.0, 4.0));
+        }
+        for pos in &screen {
+            painter.circle_filled(*pos, 3.0, color);
+        }
+        // labels only while they stay readable
+        if screen.len() > 50 {
+            return;
+        }
+        let measurement = Measurement::new(&vertices, self.layer().utm_zone);
+        for (segment, ends) in measurement.segments.iter().zip(screen.windows(2)) {
+            if ends[0].distance(ends[1]) < 60.0 {
+                continue;
+            }
+            let text = painter.layout_no_wrap(
+                format!("{} m", self.settings.format_number(segment.distance, 1)),
+                FontId::proportional(12.0),
+                Color32::BLACK,
+            );
+            let rect = Align2::CENTER_CENTER
+                .anchor_size(ends[0].lerp(ends[1], 0.5), text.size() + vec2(6.0, 2.0));
+            painter.rect_filled(rect, 2.0, Color32::from_white_alpha(200));
+            painter.galley(rect.min + vec2(3.0, 1.0), text, Color32::BLACK);
         }
+    }
 
-        let mut min_lat = f32::MAX;
-        let mut max_lat = f32::MIN;
-        let mut min_lon = f32::MAX;
-        let mut max_lon = f32::MIN;
+    /// Cursor position in lat/lon, UTM and MGRS, for the status bar.
+    fn cursor_readout(&self, position: Position) -> String {
+        let [lon, lat] = [position.x(), position.y()];
+        let decimals = self.settings.geo_decimals;
+        let mut text = format!(
+            "Lat {}  Lon {}",
+            self.settings.format_number(lat, decimals),
+            self.settings.format_number(lon, decimals)
+        );
+        if let Some((zone, band, [x, y])) = utm_reference([lon, lat]) {
+            let decimals = self.settings.utm_decimals;
+            text += &format!(
+                "    UTM {}{} {} E {} N",
+                zone,
+                band,
+                self.settings.format_number(x, decimals),
+                self.settings.format_number(y, decimals)
+            );
+        }
+        if let Some(mgrs) = mgrs([lon, lat]) {
+            text += &format!("    MGRS {}", mgrs);
+        }
+        text
+    }
 
-        for &[lon, lat] in &self.coords_geo {
-            min_lat = min_lat.min(lat);
-            max_lat = max_lat.max(lat);
-            min_lon = min_lon.min(lon);
-            max_lon = max_lon.max(lon);
+    fn show_map_menu(&mut self, ui: &mut egui::Ui, position: Position) {
+        let [lon, lat] = [position.x(), position.y()];
+        if ui.button("Add point here").clicked() {
+            ui.close_menu();
+            self.add_point_at(position);
+        }
+        ui.separator();
+        let mut copied = None;
+        if ui.button("Copy Lat/Lon").clicked() {
+            copied = Some(self.settings.format_geo(Some([lon, lat])));
+        }
+        if let Some((zone, band, [x, y])) = utm_reference([lon, lat])
+            && ui
+                .button(format!("Copy UTM (zone {}{})", zone, band))
+                .clicked()
+        {
+            copied = Some(self.settings.format_utm(Some([x, y])));
+        }
+        if let Some(mgrs) = mgrs([lon, lat])
+            && ui.button("Copy MGRS").clicked()
+        {
+            copied = Some(mgrs);
+        }
+        if let Some(text) = copied {
+            ui.close_menu();
+            match set_clipboard(formats::Unicode, &text) {
+                Ok(()) => self
+                    .status
+                    .info(format!("Copied {}", text.replace('\t', " "))),
+                Err(e) => self.status.error(Error::Clipboard(e.to_string())),
+            }
         }
+    }
+
+    /// Layer and index of the point whose marker is under `pos`, the closest
+    /// one if several are. Only markers drawn in the last frame are hit, not
+    /// the points in clusters or in hidden layers.
+    fn marker_at(&self, pos: Pos2) -> Option<(usize, usize)> {
+        self.drawn_markers
+            .iter()
+            .filter(|(l, i, _marker, _radius)| {
+                self.layers
+                    .get(*l)
+                    .is_some_and(|layer| layer.visible && *i < layer.points.len())
+            })
+            .map(|&(l, i, marker, radius)| ((l, i), marker.distance(pos), radius))
+            .filter(|(_hit, distance, radius)| *distance <= radius + 3.0)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(hit, _distance, _radius)| hit)
+    }
+
+    fn point_tooltip(&self, l: usize, i: usize) -> String {
+        let layer = &self.layers[l];
+        let point = &layer.points[i];
+        let mut text = format!("Point {}", i + 1);
+        if self.layers.len() > 1 {
+            text = format!("{}, {}", layer.name, text);
+        }
+        if let Some([lon, lat]) = point.geo {
+            let decimals = self.settings.geo_decimals;
+            text += &format!(
+                "\nLat/Lon: {}, {}",
+                self.settings.format_number(lat, decimals),
+                self.settings.format_number(lon, decimals)
+            );
+            if let (Some([x, y]), Some((zone, hemisphere))) =
+                (point.utm, layer.zone_of_point(point))
+            {
+                let decimals = self.settings.utm_decimals;
+                text += &format!(
+                    "\nUTM {} {}: {} E, {} N",
+                    zone,
+                    hemisphere,
+                    self.settings.format_number(x, decimals),
+                    self.settings.format_number(y, decimals)
+                );
+            }
+        }
+        text
+    }
+
+    fn move_selected(&mut self, up: bool) {
+        let n_points = self.layer().points.len();
+        let blocked = match up {
+            true => self.selected.first() == Some(&0),
+            false => self.selected.last() == Some(&(n_points - 1)),
+        };
+        if self.selected.is_empty() || blocked {
+            return;
+        }
+        self.record(if up {
+            "Move points up"
+        } else {
+            "Move points down"
+        });
+
+        let mut moved = BTreeSet::new();
+        let points = &mut self.layers[self.active].points;
+        if up {
+            for &i in self.selected.iter() {
+                points.swap(i - 1, i);
+                moved.insert(i - 1);
+            }
+        } else {
+            for &i in self.selected.iter().rev() {
+                points.swap(i, i + 1);
+                moved.insert(i + 1);
+            }
+        }
+        self.selected = moved;
+        self.selection_anchor = self
+            .selection_anchor
+            .map(|i| if up { i - 1 } else { i + 1 });
+        self.layer_mut().renumber_errors();
+        self.layer_mut().store_raw_rows();
+    }
+
+    fn apply_interpretation(&mut self, interpretation: Interpretation) {
+        let layer = self.layer_mut();
+        layer.set_interpretation(interpretation);
+        // part of the change that called it, in the same undo step
+        if layer.south_for_negative_northings() {
+            self.status
+                .info("Negative northings, converted in the southern hemisphere");
+        }
+
+        self.convert();
+        // a background conversion updates it when it is done
+        if !self.is_converting(self.active) {
+            self.update_previous_region(self.active);
+        }
+    }
+
+    /// Centre of the converted points of layer `l`, where the next dataset
+    /// is expected to be.
+    fn update_previous_region(&mut self, l: usize) {
+        let coords_geo: Vec<[f64; 2]> = self.layers[l].valid_geo_coords().collect();
+        if !coords_geo.is_empty() {
+            let n_points = coords_geo.len() as f64;
+            self.previous_region = Some([
+                coords_geo.iter().map(|[lon, _lat]| lon).sum::<f64>() / n_points,
+                coords_geo.iter().map(|[_lon, lat]| lat).sum::<f64>() / n_points,
+            ]);
+        }
+    }
+
+    fn axis_order_label(&self, order: AxisOrder) -> &'static str {
+        match (order, &self.layer().conversion_mode) {
+            (AxisOrder::Auto, _) => "Auto",
+            (AxisOrder::NorthFirst, ConversionMode::LatLontoUTM) => "Lat, Lon",
+            (AxisOrder::EastFirst, ConversionMode::LatLontoUTM) => "Lon, Lat",
+            (AxisOrder::NorthFirst, ConversionMode::UTMtoLatLon) => "Northing, Easting",
+            (AxisOrder::EastFirst, ConversionMode::UTMtoLatLon) => "Easting, Northing",
+        }
+    }
+
+    fn swap_axes(&mut self) {
+        if self.layer().raw_rows.is_empty() {
+            return;
+        }
+        self.record("Swap axes");
+        let layer = self.layer_mut();
+        let interpretation = layer.interpretation.swapped();
+        if layer.axis_order != AxisOrder::Auto {
+            layer.axis_order = interpretation.axis_order();
+        }
+        self.status
+            .info(format!("Axes swapped, reading as {}", interpretation));
+        self.apply_interpretation(interpretation);
+    }
 
-        let lat_range = max_lat - min_lat;
-        let lon_range = max_lon - min_lon;
-        let range = 1.3 * lat_range.max(lon_range);
+    /// Valid points of the visible layers, the ones the map is framed on.
+    fn visible_geo_coords(&self) -> impl Iterator<Item = [f64; 2]> + '_ {
+        self.layers
+            .iter()
+            .filter(|layer| layer.visible)
+            .flat_map(|layer| layer.valid_geo_coords())
+    }
+
+    /// The layers, points and format of the exports and copies.
+    fn export(&self) -> Export<'_> {
+        Export {
+            layers: self
+                .layers
+                .iter()
+                .enumerate()
+                .filter(|(l, _layer)| {
+                    self.export_scope == ExportScope::AllLayers || *l == self.active
+                })
+                .map(|(_l, layer)| layer)
+                .collect(),
+            layer_column: self.export_scope == ExportScope::AllLayers,
+            exclude_invalid: self.exclude_invalid,
+            settings: &self.settings,
+        }
+    }
 
-        TILE_WIDTHS
-            .into_iter()
-            .enumerate()
-            .find(|(_i, tw)| (range as f64 - tw) > 0.0)
-            .map_or(0, |(i, _)| i) as f64
+    fn drop_invalid_points(&mut self) {
+        self.record("Drop invalid points");
+        let layer = self.layer_mut();
+        let n_before = layer.points.len();
+        layer.points.retain(|p| p.is_valid());
+        layer.store_raw_rows();
+        let n_dropped = n_before - layer.points.len();
+        self.status
+            .info(format!("Dropped {} invalid points", n_dropped));
+        self.selected.clear();
+        self.selection_anchor = None;
     }
 
+    /// Fits the view to the visible points, once the map has a size.
     fn move_map_to_points(&mut self) {
-        if self.coords_geo.is_empty() {
+        let Some(rect) = self.map_rect else {
+            self.fit_pending = true;
             return;
+        };
+        let positions: Vec<[f64; 2]> = self.visible_geo_coords().collect();
+        let max_zoom = (self.tiles.max_zoom() as f64).min(FIT_MAX_ZOOM);
+        if let Some(([lon, lat], zoom)) = fit_view(&positions, rect.size(), FIT_PADDING, max_zoom) {
+            self.map_memory.center_at(Position::new(lon, lat));
+            let _ = self.map_memory.set_zoom(zoom);
         }
+    }
 
-        let n_points = self.coords_geo.len() as f32;
-        let (center_lon, center_lat) = if n_points > 0.0 {
-            let lat = self.coords_geo.iter().map(|[_lon, lat]| *lat).sum::<f32>() / n_points;
-            let lon = self.coords_geo.iter().map(|[lon, _lat]| *lon).sum::<f32>() / n_points;
-            (lon as f64, lat as f64)
-        } else {
-            (DEFAULT_LAT, DEFAULT_LON)
-        };
+    fn copy_coords_geo_to_clipboard(&self) -> Result<(), Error> {
+        set_clipboard(formats::Unicode, self.export().geo_text())
+            .map_err(|e| Error::Clipboard(e.to_string()))
+    }
 
-        self.map_memory
-            .center_at(Position::new(center_lon, center_lat));
+    fn copy_coords_utm_to_clipboard(&self) -> Result<(), Error> {
+        set_clipboard(formats::Unicode, self.export().utm_text())
+            .map_err(|e| Error::Clipboard(e.to_string()))
+    }
 
-        let zoom_level = self.calculate_zoom_level();
-        let _ = self.map_memory.set_zoom(zoom_level);
+    fn report_copy(&mut self, result: Result<(), Error>) {
+        match result {
+            Ok(()) => self.status.info("Copied to clipboard"),
+            Err(e) => self.status.error(e),
+        }
     }
 
-    fn copy_coords_geo_to_clipboard(&self) {
-        let data = self
-            .coords_geo
-            .iter()
-            .map(|&[lon, lat]| format!("{}\t{}", lat, lon))
-            .join("\n");
+    fn export_csv_utm(&self, outfile: &PathBuf) -> Result<(), Error> {
+        fs::write(outfile, self.export().csv_utm())?;
+        Ok(())
+    }
 
-        match set_clipboard(formats::Unicode, data) {
-            Ok(()) => println!("Copied to clipboard"),
-            Err(e) => println!("Failed to copy to clipboard: {}", e),
-        };
+    fn export_csv_latlon(&self, outfile: &PathBuf) -> Result<(), Error> {
+        fs::write(outfile, self.export().csv_latlon())?;
+        Ok(())
     }
 
-    fn copy_coords_utm_to_clipboard(&self) {
-        let data = self
-            .coords_utm
-            .iter()
-            .map(|&[x, y]| format!("{}\t{}", x, y))
-            .join("\n");
+    fn copy_coords_combined_to_clipboard(&self) -> Result<(), Error> {
+        let export = self.export();
+        set_clipboard(formats::Unicode, export.combined_table(export.rows()))
+            .map_err(|e| Error::Clipboard(e.to_string()))
+    }
 
-        match set_clipboard(formats::Unicode, data) {
-            Ok(()) => println!("Copied to clipboard"),
-            Err(e) => println!("Failed to copy to clipboard: {}", e),
-        };
+    fn copy_selection_to_clipboard(&self) -> Result<(), Error> {
+        set_clipboard(
+            formats::Unicode,
+            self.export()
+                .combined_table(self.selected.iter().map(|&i| (self.layer(), i))),
+        )
+        .map_err(|e| Error::Clipboard(e.to_string()))
     }
 
-    fn export_csv_utm(&self, outfile: &PathBuf) -> Result<(), std::io::Error> {
-        let mut file = File::create(outfile)?;
-        writeln!(file, "Easting\tNorthing")?;
-        for &[x, y] in &self.coords_utm {
-            writeln!(file, "{}\t{}", x, y)?;
-        }
-        println!("UTM coordinates exported to {:?}", outfile);
+    fn export_csv_combined(&self, outfile: &PathBuf) -> Result<(), Error> {
+        let export = self.export();
+        fs::write(outfile, export.combined_table(export.rows()) + "\n")?;
         Ok(())
     }
 
-    fn export_csv_latlon(&self, outfile: &PathBuf) -> Result<(), std::io::Error> {
-        let mut file = File::create(outfile)?;
-        writeln!(file, "Latitude\tLongitude")?;
-        for &[lon, lat] in &self.coords_geo {
-            writeln!(file, "{}\t{}", lat, lon)?;
-        }
-        println!("UTM coordinates exported to {:?}", outfile);
+    fn export_kmz(&self, outfile: &PathBuf) -> Result<(), Error> {
+        fs::write(outfile, self.export().kml())?;
         Ok(())
     }
 
-    fn export_kmz(&self, outfile: &PathBuf) -> Result<(), std::io::Error> {
-        let kml_content = format!(
-            r#"<?xml version="1.0" encoding="UTF-8"?>
-            <kml xmlns="http://www.opengis.net/kml/2.2">
-                <Document>
-                    <name>Coordinates</name>
-                    {}
-                </Document>
-            </kml>"#,
-            self.coords_geo
-                .iter()
-                .map(|&[lon, lat]| format!(
-                    r#"<Placemark>
-                        <Point>
-                            <coordinates>{},{},0</coordinates>
-                        </Point>
-                    </Placemark>"#,
-                    lon, lat
-                ))
-                .join("\n")
-        );
-
-        let mut file = File::create(outfile)?;
-        file.write_all(kml_content.as_bytes())?;
+    fn export_geojson(&self, outfile: &PathBuf) -> Result<(), Error> {
+        let file = File::create(outfile)?;
+        serde_json::to_writer_pretty(file, &self.export().geojson())
+            .map_err(|e| Error::Io(e.to_string()))?;
+        Ok(())
+    }
 
-        println!("KML file exported to {:?}", outfile);
+    fn export_dxf(&self, outfile: &PathBuf) -> Result<(), Error> {
+        fs::write(outfile, self.export().dxf())?;
         Ok(())
     }
 }
 
 impl App for CoordinatesSuite {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        if let Some(region) = self.previous_region {
+            eframe::set_value(storage, PREVIOUS_REGION_KEY, &region);
+        }
+        eframe::set_value(storage, SETTINGS_KEY, &self.settings);
+        eframe::set_value(storage, RECENT_FILES_KEY, &self.recent_files);
+        eframe::set_value(storage, EXCLUDE_INVALID_KEY, &self.exclude_invalid);
+        eframe::set_value(storage, EDIT_ON_MAP_KEY, &self.edit_on_map);
+        eframe::set_value(storage, OVERLAYS_KEY, &self.overlays);
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        // a clean exit, there is nothing to recover next time
+        if self.recovery_file.is_none() {
+            self.remove_autosave();
+        }
+    }
+
     fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
+        self.poll_conversions();
+        eframe::egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
+            self.show_menu_bar(ui);
+        });
+        eframe::egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if !self.conversions.is_empty() {
+                    ui.spinner();
+                }
+                self.status.show_bar(ui);
+                if let Some(position) = self.cursor {
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        ui.label(self.cursor_readout(position));
+                    });
+                }
+            });
+        });
+        self.status.show_window(ctx);
+
+        match self.history.show_window(ctx) {
+            Some(Jump::Undo(n)) => (0..n).for_each(|_| self.undo()),
+            Some(Jump::Redo(n)) => (0..n).for_each(|_| self.redo()),
+            None => {}
+        }
+
+        self.show_settings_window(ctx);
+        self.show_custom_tiles_window(ctx);
+        self.show_style_window(ctx);
+        self.show_prefetch_window(ctx);
+        self.show_measure_window(ctx);
+        self.show_validation_window(ctx);
+        self.show_recovery_dialog(ctx);
+        self.show_close_dialog(ctx);
+        self.autosave(ctx);
+        self.update_title(ctx);
+
+        let (save_as, save, open) = ctx.input_mut(|input| {
+            let save_as = input.consume_key(Modifiers::COMMAND | Modifiers::SHIFT, Key::S);
+            (
+                save_as,
+                input.consume_key(Modifiers::COMMAND, Key::S),
+                input.consume_key(Modifiers::COMMAND, Key::O),
+            )
+        });
+        if save_as {
+            self.save_as();
+        }
+        if save {
+            self.save();
+        }
+        if open {
+            self.open_dialog(ctx);
+        }
+
+        // text fields keep their own undo while they have the focus
+        if !ctx.wants_keyboard_input() {
+            let (undo, redo, reload) = ctx.input_mut(|input| {
+                let redo = input.consume_key(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z)
+                    || input.consume_key(Modifiers::COMMAND, Key::Y);
+                (
+                    input.consume_key(Modifiers::COMMAND, Key::Z),
+                    redo,
+                    input.consume_key(Modifiers::COMMAND, Key::R),
+                )
+            });
+            if undo {
+                self.undo();
+            }
+            if redo {
+                self.redo();
+            }
+            if reload {
+                self.reload_clipboard();
+            }
+        }
+
         eframe::egui::SidePanel::left("left_panel")
             .show_separator_line(true)
-            .exact_width(420.0)
+            .exact_width(self.settings.panel_width)
             .show(ctx, |ui| {
                 ui.add_space(10.0);
+                let mut activated = None;
+                let appearances = self.appearances();
+                for (l, layer) in self.layers.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut layer.visible, "")
+                            .on_hover_text("Show on map");
+                        ui.color_edit_button_srgba(&mut layer.color);
+                        if ui
+                            .button("Style")
+                            .on_hover_text("Marker symbology and labels")
+                            .clicked()
+                        {
+                            activated = Some(l);
+                            self.style_open = true;
+                        }
+                        if l == self.active {
+                            ui.add(TextEdit::singleline(&mut layer.name).desired_width(150.0));
+                        } else if ui
+                            .add_sized([150.0, 18.0], Button::new(&layer.name))
+                            .clicked()
+                        {
+                            activated = Some(l);
+                        }
+                        ui.label(format!("{} points", layer.points.len()));
+                    });
+                }
+                self.record_appearances(appearances);
+                if let Some(l) = activated {
+                    self.set_active_layer(l);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("New layer").clicked() {
+                        self.add_layer();
+                    }
+                    if ui
+                        .add_enabled(self.layers.len() > 1, Button::new("Delete layer"))
+                        .clicked()
+                    {
+                        self.remove_active_layer();
+                    }
+                });
+
+                ui.separator();
                 Grid::new("utm_inputs")
                     .striped(false)
                     .num_columns(3)
@@ -319,49 +1782,58 @@ impl App for CoordinatesSuite {
                     .spacing([20.0, 7.0])
                     .show(ui, |ui| {
                         ui.label("Conversion mode");
+                        let previous_mode = self.layer().conversion_mode.clone();
                         ComboBox::new("conversion_mode", "")
                             .width(130.0)
-                            .selected_text(format!("{}", self.conversion_mode))
+                            .selected_text(format!("{}", previous_mode))
                             .show_ui(ui, |ui| {
                                 for mode in
                                     [ConversionMode::LatLontoUTM, ConversionMode::UTMtoLatLon]
                                 {
                                     if ui
                                         .selectable_value(
-                                            &mut self.conversion_mode,
+                                            &mut self.layers[self.active].conversion_mode,
                                             mode.clone(),
                                             format!("{}", mode),
                                         )
                                         .clicked()
                                     {
-                                        self.parse_coordinates();
+                                        let mut before = self.snapshot();
+                                        before.layers[self.active].conversion_mode =
+                                            previous_mode.clone();
+                                        self.history.record(format!("Mode {}", mode), before);
+                                        self.set_conversion_mode(mode);
                                         self.move_map_to_points();
                                     }
                                 }
                             });
 
-                        let read_clip_button =
-                            ui.add_sized([130., 20.], Button::new("Read from clipboard"));
+                        let read_clip_button = ui
+                            .add_sized([130., 20.], Button::new("Read from clipboard"))
+                            .on_hover_text("Replace the points with the clipboard (Ctrl+R)");
                         if read_clip_button.clicked() {
-                            self.parse_coordinates();
-                            self.move_map_to_points();
+                            self.reload_clipboard();
                         }
                         ui.end_row();
 
                         ui.label("UTM Zone");
-                        let previous_utm_zone = self.utm_zone;
+                        let previous_utm_zone = self.layer().utm_zone;
                         ui.add_enabled_ui(
-                            matches!(self.conversion_mode, ConversionMode::UTMtoLatLon),
+                            matches!(self.layer().conversion_mode, ConversionMode::UTMtoLatLon),
                             |ui| {
                                 ui.add_sized(
                                     [130., 20.],
-                                    DragValue::new(&mut self.utm_zone).range(1..=60),
+                                    DragValue::new(&mut self.layers[self.active].utm_zone)
+                                        .range(1..=60),
                                 );
                             },
                         );
 
-                        if self.utm_zone != previous_utm_zone {
-                            self.parse_coordinates();
+                        if self.layer().utm_zone != previous_utm_zone {
+                            let mut before = self.snapshot();
+                            before.layers[self.active].utm_zone = previous_utm_zone;
+                            self.history.record_merged("Change UTM zone", before);
+                            self.reconvert();
                             self.move_map_to_points();
                         }
 
@@ -369,29 +1841,42 @@ impl App for CoordinatesSuite {
                             ui.add_sized([130., 20.], Button::new("Move map to points"));
                         if move_button.clicked() {
                             self.move_map_to_points();
-                            self.move_map_to_points();
                         }
 
                         ui.end_row();
 
                         ui.label("Hemisphere");
+                        let previous_hemisphere = self.layer().hemisphere.clone();
                         ui.add_enabled_ui(
-                            matches!(self.conversion_mode, ConversionMode::UTMtoLatLon),
+                            matches!(self.layer().conversion_mode, ConversionMode::UTMtoLatLon),
                             |ui| {
                                 ComboBox::new("hemisphere", "")
                                     .width(130.0)
-                                    .selected_text(format!("{}", self.hemisphere))
+                                    .selected_text(format!("{}", previous_hemisphere))
                                     .show_ui(ui, |ui| {
                                         for hemisphere in [Hemisphere::North, Hemisphere::South] {
                                             if ui
                                                 .selectable_value(
-                                                    &mut self.hemisphere,
+                                                    &mut self.layers[self.active].hemisphere,
                                                     hemisphere.clone(),
                                                     format!("{}", hemisphere),
                                                 )
                                                 .clicked()
                                             {
-                                                self.parse_coordinates();
+                                                let mut before = self.snapshot();
+                                                before.layers[self.active].hemisphere =
+                                                    previous_hemisphere.clone();
+                                                let layer = self.layer_mut();
+                                                if layer.band.is_some_and(|band| {
+                                                    band.hemisphere() != layer.hemisphere
+                                                }) {
+                                                    layer.band = None;
+                                                }
+                                                self.history.record(
+                                                    format!("Hemisphere {}", hemisphere),
+                                                    before,
+                                                );
+                                                self.reconvert();
                                                 self.move_map_to_points();
                                             }
                                         }
@@ -400,40 +1885,150 @@ impl App for CoordinatesSuite {
                         );
 
                         let kmz_button = ui.add_sized([130., 20.], Button::new("Export to kmz"));
-                        if kmz_button.clicked() {
-                            if let Some(outfile) = rfd::FileDialog::new()
+                        if kmz_button.clicked()
+                            && let Some(outfile) = self
+                                .export_dialog()
                                 .add_filter("KML files", &["kml"])
                                 .set_file_name("coordinates.kml")
                                 .save_file()
-                            {
-                                match self.export_kmz(&outfile) {
-                                    Ok(()) => println!("File exported"),
-                                    Err(_) => println!("Failed to export file"),
-                                };
-                            } else {
-                                println!("No file selected.");
-                            }
+                        {
+                            match self.export_kmz(&outfile) {
+                                Ok(()) => self
+                                    .status
+                                    .info(format!("Exported to {}", outfile.display())),
+                                Err(e) => self.status.error(e),
+                            };
                         }
 
                         ui.end_row();
 
+                        ui.label("Latitude band");
+                        let previous_band = self.layer().band;
+                        let band_text = |band: Option<LatitudeBand>| match band {
+                            Some(band) => format!("{}", band),
+                            None => "Any".to_string(),
+                        };
+                        ui.add_enabled_ui(
+                            matches!(self.layer().conversion_mode, ConversionMode::UTMtoLatLon),
+                            |ui| {
+                                ComboBox::new("band", "")
+                                    .width(130.0)
+                                    .selected_text(band_text(previous_band))
+                                    .show_ui(ui, |ui| {
+                                        for band in std::iter::once(None)
+                                            .chain(LatitudeBand::all().map(Some))
+                                        {
+                                            ui.selectable_value(
+                                                &mut self.layers[self.active].band,
+                                                band,
+                                                band_text(band),
+                                            );
+                                        }
+                                    })
+                                    .response
+                                    .on_hover_text(
+                                        "Flag the points outside the band, e.g. T in 30T",
+                                    );
+                            },
+                        );
+                        let band = self.layer().band;
+                        if band != previous_band {
+                            let mut before = self.snapshot();
+                            before.layers[self.active].band = previous_band;
+                            if let Some(band) = band {
+                                before.layers[self.active].hemisphere =
+                                    self.layer().hemisphere.clone();
+                                self.layer_mut().hemisphere = band.hemisphere();
+                            }
+                            self.history
+                                .record(format!("Latitude band {}", band_text(band)), before);
+                            self.reconvert();
+                            self.move_map_to_points();
+                        }
                         ui.label(""); //dummy
-                        ui.label(""); //dummy
+                        ui.end_row();
+
+                        ui.label("Axis order");
+                        let previous_axis_order = self.layer().axis_order;
+                        ComboBox::new("axis_order", "")
+                            .width(130.0)
+                            .selected_text(self.axis_order_label(previous_axis_order))
+                            .show_ui(ui, |ui| {
+                                for order in
+                                    [AxisOrder::Auto, AxisOrder::NorthFirst, AxisOrder::EastFirst]
+                                {
+                                    let label = self.axis_order_label(order);
+                                    ui.selectable_value(
+                                        &mut self.layers[self.active].axis_order,
+                                        order,
+                                        label,
+                                    );
+                                }
+                            });
+                        let axis_order = self.layer().axis_order;
+                        if axis_order != previous_axis_order && !self.layer().raw_rows.is_empty() {
+                            let mut before = self.snapshot();
+                            before.layers[self.active].axis_order = previous_axis_order;
+                            self.history.record(
+                                format!("Axis order {}", self.axis_order_label(axis_order)),
+                                before,
+                            );
+                            let layer = self.layer();
+                            let interpretation = match &layer.detection {
+                                Some(detection) if axis_order == AxisOrder::Auto => {
+                                    detection.best()
+                                }
+                                _ => layer.interpretation.with_axis_order(axis_order),
+                            };
+                            self.apply_interpretation(interpretation);
+                            self.move_map_to_points();
+                        }
+
+                        let swap_button = ui.add_sized([130., 20.], Button::new("Swap axes"));
+                        if swap_button.clicked() {
+                            self.swap_axes();
+                            self.move_map_to_points();
+                        }
+                        ui.end_row();
+
+                        ui.label("Geometry");
+                        let previous_geometry = self.layer().geometry;
+                        ComboBox::new("geometry", "")
+                            .width(130.0)
+                            .selected_text(format!("{}", previous_geometry))
+                            .show_ui(ui, |ui| {
+                                for geometry in Geometry::ALL {
+                                    ui.selectable_value(
+                                        &mut self.layers[self.active].geometry,
+                                        geometry,
+                                        format!("{}", geometry),
+                                    );
+                                }
+                            })
+                            .response
+                            .on_hover_text("Join the points as a line or polygon, in their order");
+                        let geometry = self.layer().geometry;
+                        if geometry != previous_geometry {
+                            let mut before = self.snapshot();
+                            before.layers[self.active].geometry = previous_geometry;
+                            self.history
+                                .record(format!("Geometry {}", geometry), before);
+                        }
                         let csv_utm_button =
                             ui.add_sized([130., 20.], Button::new("Export UTM to csv"));
-                        if csv_utm_button.clicked() {
-                            if let Some(outfile) = rfd::FileDialog::new()
+                        if csv_utm_button.clicked()
+                            && let Some(outfile) = self
+                                .export_dialog()
                                 .add_filter("CSV files", &["csv"])
                                 .set_file_name("coordinates.csv")
                                 .save_file()
-                            {
-                                match self.export_csv_utm(&outfile) {
-                                    Ok(()) => println!("File exported"),
-                                    Err(_) => println!("Failed to export file"),
-                                };
-                            } else {
-                                println!("No file selected.");
-                            }
+                        {
+                            match self.export_csv_utm(&outfile) {
+                                Ok(()) => self
+                                    .status
+                                    .info(format!("Exported to {}", outfile.display())),
+                                Err(e) => self.status.error(e),
+                            };
                         }
                         ui.end_row();
 
@@ -441,43 +2036,232 @@ impl App for CoordinatesSuite {
                         ui.label(""); //dummy
                         let csv_latlon_button =
                             ui.add_sized([130., 20.], Button::new("Export Lat/Lon to csv"));
-                        if csv_latlon_button.clicked() {
-                            if let Some(outfile) = rfd::FileDialog::new()
+                        if csv_latlon_button.clicked()
+                            && let Some(outfile) = self
+                                .export_dialog()
                                 .add_filter("CSV files", &["csv"])
                                 .set_file_name("coordinates.csv")
                                 .save_file()
-                            {
-                                match self.export_csv_latlon(&outfile) {
-                                    Ok(()) => println!("File exported"),
-                                    Err(_) => println!("Failed to export file"),
-                                };
-                            } else {
-                                println!("No file selected.");
-                            }
+                        {
+                            match self.export_csv_latlon(&outfile) {
+                                Ok(()) => self
+                                    .status
+                                    .info(format!("Exported to {}", outfile.display())),
+                                Err(e) => self.status.error(e),
+                            };
+                        }
+                        ui.end_row();
+
+                        ui.label(""); //dummy
+                        ui.label(""); //dummy
+                        let csv_combined_button =
+                            ui.add_sized([130., 20.], Button::new("Export all to csv"));
+                        if csv_combined_button.clicked()
+                            && let Some(outfile) = self
+                                .export_dialog()
+                                .add_filter("CSV files", &["csv"])
+                                .set_file_name("coordinates.csv")
+                                .save_file()
+                        {
+                            match self.export_csv_combined(&outfile) {
+                                Ok(()) => self
+                                    .status
+                                    .info(format!("Exported to {}", outfile.display())),
+                                Err(e) => self.status.error(e),
+                            };
+                        }
+                        ui.end_row();
+
+                        ui.label(""); //dummy
+                        ui.label(""); //dummy
+                        let geojson_button =
+                            ui.add_sized([130., 20.], Button::new("Export to GeoJSON"));
+                        if geojson_button.clicked()
+                            && let Some(outfile) = self
+                                .export_dialog()
+                                .add_filter("GeoJSON files", &["geojson", "json"])
+                                .set_file_name("coordinates.geojson")
+                                .save_file()
+                        {
+                            match self.export_geojson(&outfile) {
+                                Ok(()) => self
+                                    .status
+                                    .info(format!("Exported to {}", outfile.display())),
+                                Err(e) => self.status.error(e),
+                            };
+                        }
+                        ui.end_row();
+
+                        ui.label(""); //dummy
+                        ui.label(""); //dummy
+                        let dxf_button = ui
+                            .add_sized([130., 20.], Button::new("Export UTM to dxf"))
+                            .on_hover_text("Points, lines and polygons in UTM, with the heights");
+                        if dxf_button.clicked()
+                            && let Some(outfile) = self
+                                .export_dialog()
+                                .add_filter("DXF files", &["dxf"])
+                                .set_file_name("coordinates.dxf")
+                                .save_file()
+                        {
+                            match self.export_dxf(&outfile) {
+                                Ok(()) => self
+                                    .status
+                                    .info(format!("Exported to {}", outfile.display())),
+                                Err(e) => self.status.error(e),
+                            };
                         }
                         ui.end_row();
                     });
 
-                ui.add_space(20.0);
+                ui.add_space(10.0);
+
+                if let Some(detection) = self.layer().detection.clone() {
+                    ui.horizontal(|ui| {
+                        ui.label("Detected format");
+                        if !detection.is_recognised() {
+                            ui.colored_label(Color32::RED, "Not recognised");
+                            return;
+                        }
+                        let mut selected = self.layer().interpretation;
+                        let score = detection
+                            .candidates
+                            .iter()
+                            .find(|(interpretation, _score)| *interpretation == selected)
+                            .map_or(0.0, |(_interpretation, score)| *score);
+                        ComboBox::new("interpretation", "")
+                            .width(220.0)
+                            .selected_text(format!("{} ({:.0}%)", selected, 100.0 * score))
+                            .show_ui(ui, |ui| {
+                                for &(interpretation, score) in &detection.candidates {
+                                    ui.selectable_value(
+                                        &mut selected,
+                                        interpretation,
+                                        format!("{} ({:.0}%)", interpretation, 100.0 * score),
+                                    );
+                                }
+                            });
+                        if selected != self.layer().interpretation {
+                            self.record(format!("Read as {}", selected));
+                            self.apply_interpretation(selected);
+                            self.move_map_to_points();
+                        }
+                    });
+                }
+
+                ui.add_space(10.0);
+
+                ui.horizontal(|ui| {
+                    ComboBox::new("row_filter", "")
+                        .width(110.0)
+                        .selected_text(format!("{}", self.row_filter))
+                        .show_ui(ui, |ui| {
+                            for filter in [RowFilter::All, RowFilter::Valid, RowFilter::Invalid] {
+                                ui.selectable_value(
+                                    &mut self.row_filter,
+                                    filter.clone(),
+                                    format!("{}", filter),
+                                );
+                            }
+                        });
+                    if ui.button("Drop invalid").clicked() {
+                        self.drop_invalid_points();
+                    }
+                    ui.checkbox(&mut self.exclude_invalid, "Exclude invalid from exports");
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Export and copy");
+                    ComboBox::new("export_scope", "")
+                        .width(110.0)
+                        .selected_text(format!("{}", self.export_scope))
+                        .show_ui(ui, |ui| {
+                            for scope in [ExportScope::ActiveLayer, ExportScope::AllLayers] {
+                                ui.selectable_value(
+                                    &mut self.export_scope,
+                                    scope.clone(),
+                                    format!("{}", scope),
+                                );
+                            }
+                        });
+                });
+
+                ui.add_space(10.0);
+
+                ui.horizontal(|ui| {
+                    if ui.button("Insert").clicked() {
+                        self.insert_point();
+                    }
+                    let has_selection = !self.selected.is_empty();
+                    if ui
+                        .add_enabled(has_selection, Button::new("Delete"))
+                        .clicked()
+                    {
+                        self.delete_selected();
+                    }
+                    if ui.add_enabled(has_selection, Button::new("Up")).clicked() {
+                        self.move_selected(true);
+                    }
+                    if ui.add_enabled(has_selection, Button::new("Down")).clicked() {
+                        self.move_selected(false);
+                    }
+                    if ui
+                        .add_enabled(has_selection, Button::new("Copy selection"))
+                        .clicked()
+                    {
+                        let result = self.copy_selection_to_clipboard();
+                        self.report_copy(result);
+                    }
+                });
+
+                ui.add_space(10.0);
 
                 ui.horizontal(|ui| {
+                    ui.add_space(35.0);
                     if ui.button("Copy").clicked() {
-                        self.copy_coords_geo_to_clipboard();
+                        let result = self.copy_coords_geo_to_clipboard();
+                        self.report_copy(result);
                     }
-                    ui.add_space(160.0);
+                    ui.add_space(120.0);
                     if ui.button("Copy").clicked() {
-                        self.copy_coords_utm_to_clipboard();
+                        let result = self.copy_coords_utm_to_clipboard();
+                        self.report_copy(result);
+                    }
+                    ui.add_space(20.0);
+                    if ui.button("Copy all").clicked() {
+                        let result = self.copy_coords_combined_to_clipboard();
+                        self.report_copy(result);
                     }
                 });
                 ui.add_space(5.0);
-                TableBuilder::new(ui)
-                    .striped(true)
-                    .column(Column::exact(75.0))
-                    .column(Column::exact(75.0))
+                let mut edits = vec![];
+                let mut clicked_row = None;
+                let geo_decimals = self.settings.geo_decimals;
+                let utm_decimals = self.settings.utm_decimals;
+                let mut table = TableBuilder::new(ui).striped(true);
+                if let Some(i) = self.scroll_to_row.take() {
+                    // the body only holds the rows that pass the filter
+                    let row = self.layer().points[..i]
+                        .iter()
+                        .filter(|p| match self.row_filter {
+                            RowFilter::All => true,
+                            RowFilter::Valid => p.is_valid(),
+                            RowFilter::Invalid => !p.is_valid(),
+                        })
+                        .count();
+                    table = table.scroll_to_row(row, None);
+                }
+                table
                     .column(Column::exact(30.0))
                     .column(Column::exact(75.0))
                     .column(Column::exact(75.0))
+                    .column(Column::exact(10.0))
+                    .column(Column::exact(80.0))
+                    .column(Column::exact(80.0))
                     .header(20.0, |mut header| {
+                        header.col(|ui| {
+                            ui.label("#");
+                        });
                         header.col(|ui| {
                             ui.label("Latitude");
                         });
@@ -494,54 +2278,453 @@ impl App for CoordinatesSuite {
                             ui.label("Northing");
                         });
                     })
-                    .body(|mut body| {
-                        for (geoc, utmc) in izip!(&self.coords_geo, &self.coords_utm) {
-                            body.row(20.0, |mut row| {
-                                row.col(|ui| {
-                                    ui.label(format!("{:.5}", geoc[1]));
-                                });
-                                row.col(|ui| {
-                                    ui.label(format!("{:.5}", geoc[0]));
-                                });
-                                row.col(|ui| {
-                                    ui.label("");
-                                }); // dummy
-                                row.col(|ui| {
-                                    ui.label(format!("{}", utmc[0] as u64));
-                                });
-                                row.col(|ui| {
-                                    ui.label(format!("{}", utmc[1] as u64));
-                                });
+                    .body(|body| {
+                        let points = &mut self.layers[self.active].points;
+                        let shown: Vec<usize> = points
+                            .iter()
+                            .enumerate()
+                            .filter(|(_i, p)| match self.row_filter {
+                                RowFilter::All => true,
+                                RowFilter::Valid => p.is_valid(),
+                                RowFilter::Invalid => !p.is_valid(),
+                            })
+                            .map(|(i, _p)| i)
+                            .collect();
+                        // only the rows in view are laid out
+                        body.rows(20.0, shown.len(), |mut row| {
+                            let i = shown[row.index()];
+                            let point = &mut points[i];
+                            row.set_selected(self.selected.contains(&i));
+                            row.col(|ui| {
+                                let text = RichText::new(format!("{}", i + 1));
+                                let response = match &point.error {
+                                    Some(e) => ui
+                                        .add(
+                                            Label::new(text.color(Color32::LIGHT_RED))
+                                                .sense(Sense::click()),
+                                        )
+                                        .on_hover_text(e.to_string()),
+                                    None => ui.add(Label::new(text).sense(Sense::click())),
+                                };
+                                if response.clicked() {
+                                    clicked_row = Some(i);
+                                }
                             });
-                        }
+                            match &mut point.geo {
+                                Some(geo) => {
+                                    for axis in [1, 0] {
+                                        row.col(|ui| {
+                                            let old_geo = *geo;
+                                            let drag = DragValue::new(&mut geo[axis])
+                                                .fixed_decimals(geo_decimals)
+                                                .speed(0.00001);
+                                            if ui.add(drag).changed() {
+                                                edits.push((i, Side::Geo, old_geo));
+                                            }
+                                        });
+                                    }
+                                }
+                                None => {
+                                    row.col(|_ui| {});
+                                    row.col(|_ui| {});
+                                }
+                            }
+                            row.col(|ui| {
+                                ui.label("");
+                            }); // dummy
+                            match &mut point.utm {
+                                Some(utm) => {
+                                    for axis in [0, 1] {
+                                        row.col(|ui| {
+                                            let old_utm = *utm;
+                                            let drag = DragValue::new(&mut utm[axis])
+                                                .fixed_decimals(utm_decimals)
+                                                .speed(0.1);
+                                            if ui.add(drag).changed() {
+                                                edits.push((i, Side::Utm, old_utm));
+                                            }
+                                        });
+                                    }
+                                }
+                                None => {
+                                    row.col(|_ui| {});
+                                    row.col(|_ui| {});
+                                }
+                            }
+                        });
                     });
+
+                if !edits.is_empty() {
+                    // the cells are already edited, put the old values back in the snapshot
+                    let mut before = self.snapshot();
+                    for &(i, side, old) in &edits {
+                        match side {
+                            Side::Geo => before.layers[self.active].points[i].geo = Some(old),
+                            Side::Utm => before.layers[self.active].points[i].utm = Some(old),
+                        }
+                    }
+                    let label = format!("Edit point {}", edits[0].0 + 1);
+                    self.history.record_merged(label, before);
+                    let layer = self.layer_mut();
+                    for (i, side, _old) in edits {
+                        layer.reconvert_point(i, side);
+                    }
+                    layer.store_raw_rows();
+                }
+                if let Some(i) = clicked_row {
+                    let modifiers = ui.input(|input| input.modifiers);
+                    self.select_row(i, modifiers);
+                }
             });
 
         eframe::egui::CentralPanel::default().show(ctx, |ui| {
-            let map_response = ui.add(Map::new(
-                Some(&mut self.tiles),
-                &mut self.map_memory,
-                lon_lat(DEFAULT_LON, DEFAULT_LAT),
-            ));
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(self.history.can_undo(), Button::new("Undo"))
+                    .on_hover_text("Ctrl+Z")
+                    .clicked()
+                {
+                    self.undo();
+                }
+                if ui
+                    .add_enabled(self.history.can_redo(), Button::new("Redo"))
+                    .on_hover_text("Ctrl+Y")
+                    .clicked()
+                {
+                    self.redo();
+                }
+                if ui.button("History").clicked() {
+                    self.history.window_open = !self.history.window_open;
+                }
+                ui.separator();
+                if ui
+                    .selectable_label(self.measure.window_open, "Measure")
+                    .on_hover_text("Distance, azimuth and area")
+                    .clicked()
+                {
+                    self.measure.window_open = !self.measure.window_open;
+                }
+                if ui
+                    .selectable_label(self.validation.window_open, "Validate")
+                    .on_hover_text("Round trip of the conversion, for QA")
+                    .clicked()
+                {
+                    self.validation.window_open = !self.validation.window_open;
+                }
+                ui.checkbox(&mut self.edit_on_map, "Edit points on map");
+                if self.edit_on_map {
+                    ui.label("Click to add, drag to move, right-click to delete");
+                }
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui
+                        .button("Prefetch...")
+                        .on_hover_text("Download the tiles in view for offline use")
+                        .clicked()
+                    {
+                        self.prefetch_open = !self.prefetch_open;
+                    }
+                    ui.menu_button("Overlays", |ui| self.overlays.show_menu(ui));
+                    let mut base_map = self.base_map.clone();
+                    ComboBox::new("base_map", "")
+                        .selected_text(format!("{}", base_map))
+                        .show_ui(ui, |ui| {
+                            for preset in BaseMap::PRESETS {
+                                ui.selectable_value(
+                                    &mut base_map,
+                                    preset.clone(),
+                                    format!("{}", preset),
+                                );
+                            }
+                            let custom = BaseMap::Custom(self.settings.custom_tiles.clone());
+                            if !self.settings.custom_tiles.url.is_empty() {
+                                ui.selectable_value(
+                                    &mut base_map,
+                                    custom.clone(),
+                                    format!("{}", custom),
+                                );
+                            }
+                            if ui.button("Custom service...").clicked() {
+                                self.custom_tiles_open = true;
+                            }
+                            ui.separator();
+                            if ui.button("Open MBTiles...").clicked()
+                                && let Some(mbtiles) = self.pick_mbtiles()
+                            {
+                                base_map = mbtiles;
+                            }
+                            if ui.button("Open tile folder...").clicked()
+                                && let Some(path) = rfd::FileDialog::new().pick_folder()
+                            {
+                                base_map = BaseMap::Folder(path);
+                            }
+                        });
+                    ui.label("Base map:");
+                    self.set_base_map(ui.ctx(), base_map);
+                });
+            });
 
-            if !self.coords_geo.is_empty() {
-                let projector = Projector::new(
-                    map_response.rect,
-                    &self.map_memory,
-                    Position::new(self.coords_geo[0][0] as f64, self.coords_geo[0][1] as f64),
-                );
+            let my_position = self.home();
+            let pointer = ui.input(|input| input.pointer.hover_pos());
 
-                let painter = ui.painter_at(map_response.rect);
-                for &[lon, lat] in &self.coords_geo {
-                    let pos = Position::new(lon as f64, lat as f64);
-                    let pos_proj = projector.project(pos);
-                    painter.circle(
-                        pos_proj.to_pos2(),
-                        5.0,
-                        Color32::RED,
-                        Stroke::new(1.0, Color32::BLACK),
-                    );
+            // the map must not pan while a marker is being dragged
+            let over_marker = pointer.and_then(|pos| self.marker_at(pos)).is_some();
+            let drag_map = !(self.edit_on_map && (over_marker || self.dragged_point.is_some()));
+
+            let map_response = ui.add(
+                Map::new(Some(&mut self.tiles), &mut self.map_memory, my_position)
+                    .drag_gesture(drag_map),
+            );
+            self.map_rect = Some(map_response.rect);
+            if std::mem::take(&mut self.fit_pending) {
+                self.move_map_to_points();
+            }
+
+            let projector = Projector::new(map_response.rect, &self.map_memory, my_position);
+            let hovered = pointer
+                .filter(|pos| map_response.rect.contains(*pos))
+                .and_then(|pos| self.marker_at(pos));
+
+            if self.edit_on_map {
+                if map_response.drag_started()
+                    && let Some((l, i)) = hovered
+                {
+                    self.set_active_layer(l);
+                    self.dragged_point = Some(i);
+                    self.record(format!("Move point {}", i + 1));
+                }
+                if let Some(i) = self.dragged_point {
+                    self.validation.invalidate();
+                    let layer = self.layer_mut();
+                    if let Some(pos) = map_response.interact_pointer_pos() {
+                        let position = projector.unproject(pos.to_vec2());
+                        layer.points[i].geo = Some([position.x(), position.y()]);
+                        layer.reconvert_point(i, Side::Geo);
+                    }
+                    if map_response.drag_stopped() {
+                        layer.store_raw_rows();
+                        self.dragged_point = None;
+                    }
+                }
+                if map_response.secondary_clicked()
+                    && let Some((l, i)) = hovered
+                {
+                    self.set_active_layer(l);
+                    self.delete_point(i);
+                }
+            }
+
+            self.cursor = pointer
+                .filter(|pos| map_response.rect.contains(*pos))
+                .map(|pos| projector.unproject(pos.to_vec2()));
+            // in edit mode a right-click on a marker deletes it instead
+            if map_response.secondary_clicked() && !(self.edit_on_map && hovered.is_some()) {
+                self.map_menu_position = self.cursor;
+            }
+            if let Some(position) = self.map_menu_position
+                && map_response
+                    .context_menu(|ui| self.show_map_menu(ui, position))
+                    .is_none()
+            {
+                self.map_menu_position = None;
+            }
+
+            if map_response.clicked() && self.measure.is_drawing() {
+                let vertex = match hovered {
+                    Some((l, i)) => self.layers[l].points[i]
+                        .geo
+                        .map(|[lon, lat]| Position::new(lon, lat)),
+                    None => self.cursor,
+                };
+                if let Some(vertex) = vertex {
+                    self.measure.vertices.push([vertex.x(), vertex.y()]);
                 }
+            } else if map_response.clicked() {
+                match hovered {
+                    Some((l, i)) => {
+                        self.set_active_layer(l);
+                        let modifiers = ui.input(|input| input.modifiers);
+                        self.select_row(i, modifiers);
+                        self.scroll_to_row = Some(i);
+                    }
+                    None if self.edit_on_map => {
+                        if let Some(pos) = map_response.interact_pointer_pos() {
+                            self.add_point_at(projector.unproject(pos.to_vec2()));
+                        }
+                    }
+                    None => {}
+                }
+            }
+
+            let painter = ui.painter_at(map_response.rect);
+            let layer = self.layer();
+            self.overlays
+                .draw(&painter, &projector, layer.utm_zone, &layer.hemisphere);
+
+            if self.measure.window_open {
+                self.draw_measurement(&painter, &projector);
+            }
+
+            // the active layer is drawn last, on top of the others
+            let draw_order: Vec<usize> = (0..self.layers.len())
+                .filter(|&l| l != self.active && self.layers[l].visible)
+                .chain(
+                    [self.active]
+                        .into_iter()
+                        .filter(|&l| self.layers[l].visible),
+                )
+                .collect();
+            let clip = painter.clip_rect();
+            // points well outside the view are not even projected
+            let center_lon = projector.unproject(clip.center().to_vec2()).x();
+            let north_west = projector.unproject(clip.left_top().to_vec2());
+            let south_east = projector.unproject(clip.right_bottom().to_vec2());
+            let [lon_margin, lat_margin] = [
+                0.1 * (south_east.x() - north_west.x()),
+                0.1 * (north_west.y() - south_east.y()),
+            ];
+            let in_bounds = |lon: f64, lat: f64| {
+                let lon = wrap_lon(lon, center_lon);
+                south_east.x() - north_west.x() >= 360.0
+                    || (north_west.x() - lon_margin..=south_east.x() + lon_margin).contains(&lon)
+                        && (south_east.y() - lat_margin..=north_west.y() + lat_margin)
+                            .contains(&lat)
+            };
+            let mut labels = LabelPlacer::default();
+            // screen position and radius of the markers in view, per layer
+            let mut markers: Vec<Vec<(usize, Pos2, f32)>> = vec![];
+            for &l in &draw_order {
+                let layer = &self.layers[l];
+                if layer.geometry != Geometry::Points {
+                    let vertices: Vec<Pos2> = layer
+                        .valid_geo_coords()
+                        .map(|[lon, lat]| project_near(&projector, center_lon, [lon, lat]))
+                        .collect();
+                    layer.geometry.paint(&painter, &vertices, layer.color);
+                }
+                let height_range = layer.height_range();
+                let margin = layer.marker_size + 2.0;
+                let visible: Vec<(usize, Pos2)> = layer
+                    .points
+                    .iter()
+                    .enumerate()
+                    .filter(|(_i, p)| p.is_valid())
+                    .filter_map(|(i, p)| p.geo.map(|geo| (i, geo)))
+                    .filter(|(_i, [lon, lat])| in_bounds(*lon, *lat))
+                    .map(|(i, [lon, lat])| {
+                        let pos = project_near(&projector, center_lon, [lon, lat]);
+                        (i, pos)
+                    })
+                    .filter(|(_i, pos)| clip.expand(margin).contains(*pos))
+                    .collect();
+                let mut in_view = vec![];
+                let mut clusters: HashMap<(i32, i32), Vec<(usize, Pos2)>> = HashMap::new();
+                let cluster = self.settings.cluster_markers && visible.len() > CLUSTER_POINTS;
+                for (i, pos) in visible {
+                    let selected = l == self.active && self.selected.contains(&i);
+                    if cluster && !selected {
+                        let cell = (
+                            (pos.x / CLUSTER_CELL).floor() as i32,
+                            (pos.y / CLUSTER_CELL).floor() as i32,
+                        );
+                        clusters.entry(cell).or_default().push((i, pos));
+                        continue;
+                    }
+                    in_view.push((i, pos));
+                }
+                for members in clusters.into_values() {
+                    if let [single] = members[..] {
+                        in_view.push(single);
+                        continue;
+                    }
+                    let center = members.iter().fold(Pos2::ZERO, |sum, (_i, pos)| {
+                        sum + pos.to_vec2() / members.len() as f32
+                    });
+                    let radius = paint_cluster(&painter, center, members.len(), layer.color);
+                    labels.block(Rect::from_center_size(
+                        center,
+                        vec2(2.0 * radius, 2.0 * radius),
+                    ));
+                }
+                let mut drawn = vec![];
+                for (i, pos) in in_view {
+                    let (radius, fill) = if l == self.active && self.selected.contains(&i) {
+                        (layer.marker_size + 2.0, Color32::YELLOW)
+                    } else {
+                        (
+                            layer.marker_size,
+                            layer.marker_color(&layer.points[i], height_range),
+                        )
+                    };
+                    layer.marker.paint(&painter, pos, radius, fill);
+                    labels.block(Rect::from_center_size(
+                        pos,
+                        vec2(2.0 * radius, 2.0 * radius),
+                    ));
+                    drawn.push((i, pos, radius));
+                }
+                markers.push(drawn);
+            }
+            self.drawn_markers = draw_order
+                .iter()
+                .zip(&markers)
+                .flat_map(|(&l, drawn)| {
+                    drawn
+                        .iter()
+                        .map(move |&(i, pos, radius)| (l, i, pos, radius))
+                })
+                .collect();
+            // labels of the active layer are placed first, where there is room
+            for (&l, in_view) in draw_order.iter().zip(&markers).rev() {
+                let layer = &self.layers[l];
+                // vertex numbers of lines and polygons, while they stay readable
+                let label = match layer.label {
+                    PointLabel::None
+                        if layer.geometry != Geometry::Points && layer.points.len() <= 300 =>
+                    {
+                        PointLabel::Number
+                    }
+                    label => label,
+                };
+                for &(i, pos, radius) in in_view {
+                    if labels.is_full() {
+                        break;
+                    }
+                    let text = match label {
+                        PointLabel::None => break,
+                        PointLabel::Number => format!("{}", i + 1),
+                        PointLabel::Height => self.settings.format_height(layer.points[i].height),
+                    };
+                    labels.place(&painter, pos, radius, text);
+                }
+            }
+
+            let attribution = self.tiles.attribution().text;
+            if !attribution.is_empty() {
+                let text = painter.layout_no_wrap(
+                    attribution.to_string(),
+                    FontId::proportional(11.0),
+                    Color32::BLACK,
+                );
+                let rect = Align2::RIGHT_BOTTOM.anchor_size(
+                    map_response.rect.right_bottom() - vec2(2.0, 2.0),
+                    text.size() + vec2(6.0, 2.0),
+                );
+                painter.rect_filled(rect, 2.0, Color32::from_white_alpha(180));
+                painter.galley(rect.min + vec2(3.0, 1.0), text, Color32::BLACK);
+            }
+
+            if let Some(place) = &self.search_result {
+                let pos = project_near(&projector, center_lon, place.position);
+                let stroke = egui::Stroke::new(2.5, Color32::from_rgb(220, 0, 120));
+                painter.circle_stroke(pos, 9.0, stroke);
+                painter.line_segment([pos - vec2(14.0, 0.0), pos + vec2(14.0, 0.0)], stroke);
+                painter.line_segment([pos - vec2(0.0, 14.0), pos + vec2(0.0, 14.0)], stroke);
+            }
+            self.show_search(ui.ctx(), map_response.rect);
+
+            if let Some((l, i)) = hovered.filter(|_| self.dragged_point.is_none()) {
+                let tooltip = self.point_tooltip(l, i);
+                map_response.on_hover_text_at_pointer(tooltip);
             }
         });
     }
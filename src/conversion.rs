@@ -0,0 +1,720 @@
+use crate::error::Error;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::LazyLock;
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum ConversionMode {
+    UTMtoLatLon,
+    LatLontoUTM,
+}
+
+impl fmt::Display for ConversionMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionMode::UTMtoLatLon => write!(f, "UTM to Lat/Lon"),
+            ConversionMode::LatLontoUTM => write!(f, "Lat/Lon to UTM"),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum Hemisphere {
+    North,
+    South,
+}
+
+impl fmt::Display for Hemisphere {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hemisphere::North => write!(f, "North"),
+            Hemisphere::South => write!(f, "South"),
+        }
+    }
+}
+
+impl Hemisphere {
+    pub fn of(lat: f64) -> Self {
+        if lat >= 0.0 {
+            Hemisphere::North
+        } else {
+            Hemisphere::South
+        }
+    }
+
+    pub fn false_northing(&self) -> f64 {
+        match self {
+            Hemisphere::North => 0.0,
+            Hemisphere::South => FALSE_NORTHING,
+        }
+    }
+
+    /// Northings from the equator to 84°N, or from 80°S to the equator with
+    /// the false northing.
+    pub fn northings(&self) -> RangeInclusive<f64> {
+        match self {
+            Hemisphere::North => 0.0..=9_400_000.0,
+            Hemisphere::South => 1_100_000.0..=FALSE_NORTHING,
+        }
+    }
+
+    /// The northing without the false northing, negative south of the
+    /// equator. Negative southern northings are taken as given without it.
+    /// `None` outside the northings of the hemisphere.
+    pub fn signed_northing(&self, y: f64) -> Option<f64> {
+        match self {
+            Hemisphere::North => self.northings().contains(&y).then_some(y),
+            Hemisphere::South => {
+                let y = if y < 0.0 { y + FALSE_NORTHING } else { y };
+                self.northings().contains(&y).then_some(y - FALSE_NORTHING)
+            }
+        }
+    }
+}
+
+/// Latitude bands of the UTM grid from 80°S, 8° each but X, which reaches 84°N.
+const BANDS: &str = "CDEFGHJKLMNPQRSTUVWX";
+
+/// A latitude band, the letter after the zone number as in "30T".
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "char", into = "char")]
+pub struct LatitudeBand(char);
+
+impl LatitudeBand {
+    pub fn all() -> impl Iterator<Item = LatitudeBand> {
+        BANDS.chars().map(LatitudeBand)
+    }
+
+    pub fn new(letter: char) -> Option<Self> {
+        let letter = letter.to_ascii_uppercase();
+        BANDS.contains(letter).then_some(LatitudeBand(letter))
+    }
+
+    /// The band of a latitude, `None` outside 80°S to 84°N.
+    pub fn of(lat: f64) -> Option<Self> {
+        if !(-80.0..=84.0).contains(&lat) {
+            return None;
+        }
+        let i = (((lat + 80.0) / 8.0) as usize).min(BANDS.len() - 1);
+        BANDS.chars().nth(i).map(LatitudeBand)
+    }
+
+    pub fn hemisphere(&self) -> Hemisphere {
+        if self.0 >= 'N' {
+            Hemisphere::North
+        } else {
+            Hemisphere::South
+        }
+    }
+
+    /// Southern and northern limits, in degrees.
+    pub fn latitudes(&self) -> [f64; 2] {
+        let south = -80.0 + 8.0 * BANDS.find(self.0).unwrap_or_default() as f64;
+        let north = if self.0 == 'X' { 84.0 } else { south + 8.0 };
+        [south, north]
+    }
+}
+
+impl TryFrom<char> for LatitudeBand {
+    type Error = String;
+
+    fn try_from(letter: char) -> Result<Self, Self::Error> {
+        LatitudeBand::new(letter).ok_or_else(|| format!("invalid latitude band {}", letter))
+    }
+}
+
+impl From<LatitudeBand> for char {
+    fn from(band: LatitudeBand) -> char {
+        band.0
+    }
+}
+
+impl fmt::Display for LatitudeBand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A single coordinate in both systems. The side that was not given as input
+/// stays `None` until it is converted, or when its conversion failed.
+#[derive(Debug, Clone)]
+pub struct Point {
+    pub geo: Option<[f64; 2]>,
+    pub utm: Option<[f64; 2]>,
+    pub height: f64,
+    pub error: Option<Error>,
+}
+
+impl Point {
+    pub fn from_geo(lon: f64, lat: f64, height: f64) -> Self {
+        Point {
+            geo: Some([lon, lat]),
+            utm: None,
+            height,
+            error: None,
+        }
+    }
+
+    pub fn from_utm(x: f64, y: f64, height: f64) -> Self {
+        Point {
+            geo: None,
+            utm: Some([x, y]),
+            height,
+            error: None,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// A number with a point or comma decimal separator and an optional exponent
+static NUMBER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"([+-]?\d+([.,]\d+)?([eE][+-]?\d+)?)").unwrap());
+
+/// The numbers of one line, in order.
+pub fn parse_numbers(line: &str) -> Vec<f64> {
+    NUMBER
+        .find_iter(line)
+        .filter_map(|m| m.as_str().replace(',', ".").parse::<f64>().ok())
+        .collect()
+}
+
+pub fn parse_number_rows(input: &str) -> (Vec<[f64; 3]>, Vec<Error>) {
+    let mut rows = vec![];
+    let mut errors = vec![];
+    for (i, line) in input.lines().enumerate() {
+        let numbers = parse_numbers(line);
+
+        // a line with exactly three numbers carries a height after the pair,
+        // lines without numbers (headers, blank lines) are skipped
+        match numbers.len() {
+            0 => {}
+            3 => rows.push([numbers[0], numbers[1], numbers[2]]),
+            n if n % 2 == 0 => rows.extend(
+                numbers
+                    .chunks_exact(2)
+                    .map(|chunk| [chunk[0], chunk[1], 0.0]),
+            ),
+            _ => errors.push(Error::Parse {
+                line: i + 1,
+                text: line.trim().to_string(),
+            }),
+        }
+    }
+
+    (rows, errors)
+}
+
+/// WGS 84 semi-major axis and flattening
+pub const A: f64 = 6_378_137.0;
+pub const F: f64 = 1.0 / 298.257_223_563;
+/// Scale on the central meridian of a UTM zone
+const K0: f64 = 0.9996;
+const FALSE_EASTING: f64 = 500_000.0;
+/// Added to the northings of the southern hemisphere
+const FALSE_NORTHING: f64 = 10_000_000.0;
+/// Latitude by which a position may fall outside its band, about a metre
+const BAND_TOLERANCE: f64 = 1e-5;
+
+/// Rectifying radius and the coefficients of Krüger's series, to the sixth
+/// order of the third flattening: forward (α) and inverse (β).
+fn kruger_series() -> (f64, [f64; 6], [f64; 6]) {
+    let n = F / (2.0 - F);
+    let [n2, n3, n4, n5, n6] = [2, 3, 4, 5, 6].map(|p| n.powi(p));
+    let radius = A / (1.0 + n) * (1.0 + n2 / 4.0 + n4 / 64.0 + n6 / 256.0);
+    let alpha = [
+        n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0 + 41.0 * n4 / 180.0 - 127.0 * n5 / 288.0
+            + 7891.0 * n6 / 37800.0,
+        13.0 * n2 / 48.0 - 3.0 * n3 / 5.0 + 557.0 * n4 / 1440.0 + 281.0 * n5 / 630.0
+            - 1983433.0 * n6 / 1935360.0,
+        61.0 * n3 / 240.0 - 103.0 * n4 / 140.0 + 15061.0 * n5 / 26880.0 + 167603.0 * n6 / 181440.0,
+        49561.0 * n4 / 161280.0 - 179.0 * n5 / 168.0 + 6601661.0 * n6 / 7257600.0,
+        34729.0 * n5 / 80640.0 - 3418889.0 * n6 / 1995840.0,
+        212378941.0 * n6 / 319334400.0,
+    ];
+    let beta = [
+        n / 2.0 - 2.0 * n2 / 3.0 + 37.0 * n3 / 96.0 - n4 / 360.0 - 81.0 * n5 / 512.0
+            + 96199.0 * n6 / 604800.0,
+        n2 / 48.0 + n3 / 15.0 - 437.0 * n4 / 1440.0 + 46.0 * n5 / 105.0
+            - 1118711.0 * n6 / 3870720.0,
+        17.0 * n3 / 480.0 - 37.0 * n4 / 840.0 - 209.0 * n5 / 4480.0 + 5569.0 * n6 / 90720.0,
+        4397.0 * n4 / 161280.0 - 11.0 * n5 / 504.0 - 830251.0 * n6 / 7257600.0,
+        4583.0 * n5 / 161280.0 - 108847.0 * n6 / 3991680.0,
+        20648693.0 * n6 / 638668800.0,
+    ];
+    (radius, alpha, beta)
+}
+
+fn central_meridian(zone: u8) -> f64 {
+    zone as f64 * 6.0 - 183.0
+}
+
+/// Transverse Mercator [easting, northing] of a [lon, lat] position in a UTM
+/// zone, with the northing negative south of the equator (no false northing).
+/// Within a millimetre well beyond the zone limits.
+pub fn project(zone: u8, [lon, lat]: [f64; 2]) -> [f64; 2] {
+    let (radius, alpha, _) = kruger_series();
+    let e = (F * (2.0 - F)).sqrt();
+    let (phi, lambda) = (
+        lat.to_radians(),
+        (lon - central_meridian(zone)).to_radians(),
+    );
+    // conformal latitude, as its tangent
+    let tau = (phi.sin().atanh() - e * (e * phi.sin()).atanh()).sinh();
+    let xi_ = tau.atan2(lambda.cos());
+    let eta_ = (lambda.sin() / tau.hypot(lambda.cos())).asinh();
+    let (mut xi, mut eta) = (xi_, eta_);
+    for (j, a) in alpha.iter().enumerate() {
+        let k = 2.0 * (j + 1) as f64;
+        xi += a * (k * xi_).sin() * (k * eta_).cosh();
+        eta += a * (k * xi_).cos() * (k * eta_).sinh();
+    }
+    [FALSE_EASTING + K0 * radius * eta, K0 * radius * xi]
+}
+
+/// Inverse of [`project`]: the [lon, lat] position of an [easting, northing]
+/// without false northing.
+pub fn unproject(zone: u8, [x, y]: [f64; 2]) -> [f64; 2] {
+    let (radius, _, beta) = kruger_series();
+    let e2 = F * (2.0 - F);
+    let e = e2.sqrt();
+    let (xi, eta) = (y / (K0 * radius), (x - FALSE_EASTING) / (K0 * radius));
+    let (mut xi_, mut eta_) = (xi, eta);
+    for (j, b) in beta.iter().enumerate() {
+        let k = 2.0 * (j + 1) as f64;
+        xi_ -= b * (k * xi).sin() * (k * eta).cosh();
+        eta_ -= b * (k * xi).cos() * (k * eta).sinh();
+    }
+    let conformal = xi_.sin() / eta_.sinh().hypot(xi_.cos());
+    let lambda = eta_.sinh().atan2(xi_.cos());
+    // the geographic latitude from the conformal one, by Newton's method
+    let mut tau = conformal;
+    for _ in 0..5 {
+        let sigma = (e * (e * tau / tau.hypot(1.0)).atanh()).sinh();
+        let tau_i = tau * sigma.hypot(1.0) - sigma * tau.hypot(1.0);
+        let step = (conformal - tau_i) / tau_i.hypot(1.0) * (1.0 + (1.0 - e2) * tau * tau)
+            / ((1.0 - e2) * tau.hypot(1.0));
+        tau += step;
+        if step.abs() < 1e-12 {
+            break;
+        }
+    }
+    [
+        central_meridian(zone) + lambda.to_degrees(),
+        tau.atan().to_degrees(),
+    ]
+}
+
+/// Converts to lat/lon. Northings are checked against the range of the
+/// hemisphere, and southern ones may be given negative, without the false
+/// northing.
+pub fn utm_to_geo(
+    point: usize,
+    [x, y]: [f64; 2],
+    zone: u8,
+    hemisphere: &Hemisphere,
+) -> Result<[f64; 2], Error> {
+    if !(1..=60).contains(&zone) {
+        return Err(Error::InvalidZone(zone));
+    }
+    if !(100_000.0..1_000_000.0).contains(&x) {
+        return Err(Error::OutOfRange {
+            point,
+            coordinate: "Easting",
+            value: x,
+        });
+    }
+    let Some(y) = hemisphere.signed_northing(y) else {
+        return Err(Error::OutOfRange {
+            point,
+            coordinate: "Northing",
+            value: y,
+        });
+    };
+    Ok(unproject(zone, [x, y]))
+}
+
+/// Converts to lat/lon in the hemisphere of a latitude band, and checks that
+/// the position falls in the band.
+pub fn utm_band_to_geo(
+    point: usize,
+    utm: [f64; 2],
+    zone: u8,
+    band: LatitudeBand,
+) -> Result<[f64; 2], Error> {
+    let [lon, lat] = utm_to_geo(point, utm, zone, &band.hemisphere())?;
+    let [south, north] = band.latitudes();
+    // positions on a band limit may round to either side of it
+    if !(south - BAND_TOLERANCE..=north + BAND_TOLERANCE).contains(&lat) {
+        return Err(Error::OutsideBand {
+            point,
+            band: band.into(),
+            latitude: lat,
+        });
+    }
+    Ok([lon, lat])
+}
+
+/// UTM zone and hemisphere a [lon, lat] position falls in, with the wider
+/// zones of south-western Norway (32V) and Svalbard (31X, 33X, 35X and 37X).
+pub fn zone_of([lon, lat]: [f64; 2]) -> (u8, Hemisphere) {
+    let zone = if (56.0..64.0).contains(&lat) && (3.0..12.0).contains(&lon) {
+        32
+    } else if (72.0..=84.0).contains(&lat) && (0.0..42.0).contains(&lon) {
+        match lon {
+            ..9.0 => 31,
+            ..21.0 => 33,
+            ..33.0 => 35,
+            _ => 37,
+        }
+    } else {
+        // 180° is the meridian of -180°, the west edge of zone 1
+        ((lon + 180.0).rem_euclid(360.0) / 6.0) as u8 + 1
+    };
+    (zone, Hemisphere::of(lat))
+}
+
+/// UTM [easting, northing] of a [lon, lat] position in `zone`, with the false
+/// northing south of the equator.
+fn to_utm(zone: u8, position: [f64; 2]) -> [f64; 2] {
+    let [x, y] = project(zone, position);
+    if position[1] < 0.0 {
+        [x, y + FALSE_NORTHING]
+    } else {
+        [x, y]
+    }
+}
+
+/// Converts to UTM in the given zone, or in the zone the point falls in when
+/// there is none. UTM is only defined between 80°S and 84°N.
+pub fn geo_to_utm(point: usize, [lon, lat]: [f64; 2], zone: Option<u8>) -> Result<[f64; 2], Error> {
+    if !(-80.0..=84.0).contains(&lat) {
+        return Err(Error::OutOfRange {
+            point,
+            coordinate: "Latitude",
+            value: lat,
+        });
+    }
+    if !(-180.0..180.0).contains(&lon) {
+        return Err(Error::OutOfRange {
+            point,
+            coordinate: "Longitude",
+            value: lon,
+        });
+    }
+
+    let zone = zone.unwrap_or_else(|| zone_of([lon, lat]).0);
+    Ok(to_utm(zone, [lon, lat]))
+}
+
+/// Zone, latitude band and [easting, northing] of a [lon, lat] position, in
+/// the zone it falls in. `None` outside 80°S to 84°N.
+pub fn utm_reference([lon, lat]: [f64; 2]) -> Option<(u8, LatitudeBand, [f64; 2])> {
+    let band = LatitudeBand::of(lat)?;
+    let (zone, _hemisphere) = zone_of([lon, lat]);
+    Some((zone, band, to_utm(zone, [lon, lat])))
+}
+
+/// MGRS reference of a [lon, lat] position to the metre, e.g. "30T XM 76543 12345".
+pub fn mgrs(position: [f64; 2]) -> Option<String> {
+    const COLUMNS: [&[u8]; 3] = [b"ABCDEFGH", b"JKLMNPQR", b"STUVWXYZ"];
+    const ROWS: &[u8] = b"ABCDEFGHJKLMNPQRSTUV";
+
+    let (zone, band, [easting, northing]) = utm_reference(position)?;
+    let column_index = ((easting / 100_000.0) as usize).checked_sub(1)?;
+    let column = COLUMNS[(zone as usize - 1) % 3].get(column_index)?;
+    // even zones start their rows at F
+    let row_offset = if zone % 2 == 0 { 5 } else { 0 };
+    let row = ROWS[((northing / 100_000.0) as usize + row_offset) % ROWS.len()];
+    Some(format!(
+        "{}{} {}{} {:05} {:05}",
+        zone,
+        band,
+        *column as char,
+        row as char,
+        (easting % 100_000.0) as u32,
+        (northing % 100_000.0) as u32
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measure::inverse;
+    use proptest::prelude::*;
+
+    /// [lat, lon], zone and [easting, northing] to the metre, from published
+    /// conversions.
+    const REFERENCE_POINTS: [([f64; 2], u8, [f64; 2]); 7] = [
+        // Aachen
+        ([50.77535, 6.08389], 32, [294409.0, 5628898.0]),
+        // New York
+        ([40.71435, -74.00597], 18, [583960.0, 4507523.0]),
+        // Wellington
+        ([-41.28646, 174.77624], 60, [313784.0, 5427057.0]),
+        // Cape Town
+        ([-33.92487, 18.42406], 34, [261878.0, 6243186.0]),
+        // Mendoza
+        ([-32.89018, -68.84405], 19, [514586.0, 6360877.0]),
+        // Fairbanks
+        ([64.83778, -147.71639], 6, [466013.0, 7190568.0]),
+        // Ben Nevis
+        ([56.79680, -5.00601], 30, [377486.0, 6296562.0]),
+    ];
+
+    fn distance(a: [f64; 2], b: [f64; 2]) -> f64 {
+        inverse(a, b).unwrap().0
+    }
+
+    #[test]
+    fn test_parse_number_rows() {
+        let (rows, errors) = parse_number_rows(
+            "Lat\tLon\n41.65 -0.87\n\n41,5;-0,5;210,5\n676000 4610000 677000 4611000\n1e3 -2.5E+2",
+        );
+        assert!(errors.is_empty());
+        assert_eq!(
+            rows,
+            vec![
+                [41.65, -0.87, 0.0],
+                [41.5, -0.5, 210.5],
+                [676000.0, 4610000.0, 0.0],
+                [677000.0, 4611000.0, 0.0],
+                [1000.0, -250.0, 0.0],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_number_rows_errors() {
+        let (rows, errors) = parse_number_rows("1 2\n1 2 3 4 5\n3 4");
+        assert_eq!(rows, vec![[1.0, 2.0, 0.0], [3.0, 4.0, 0.0]]);
+        assert!(matches!(&errors[..], [Error::Parse { line: 2, .. }]));
+    }
+
+    #[test]
+    fn test_reference_points() {
+        for ([lat, lon], zone, expected) in REFERENCE_POINTS {
+            assert_eq!(zone_of([lon, lat]).0, zone);
+            let [x, y] = geo_to_utm(0, [lon, lat], None).unwrap();
+            assert!((x - expected[0]).abs() < 0.5, "{} {}: {}", lat, lon, x);
+            assert!((y - expected[1]).abs() < 0.5, "{} {}: {}", lat, lon, y);
+
+            let hemisphere = if lat < 0.0 {
+                Hemisphere::South
+            } else {
+                Hemisphere::North
+            };
+            let back = utm_to_geo(0, expected, zone, &hemisphere).unwrap();
+            assert!(distance(back, [lon, lat]) < 1.0, "{} {}", lat, lon);
+        }
+    }
+
+    #[test]
+    fn test_central_meridian_and_equator() {
+        let [x, y] = geo_to_utm(0, [-3.0, 0.0], None).unwrap();
+        assert!((x - 500_000.0).abs() < 1e-6);
+        assert!(y.abs() < 1e-6);
+        // just south of the equator the false northing applies
+        let [_x, y] = geo_to_utm(0, [-3.0, -1e-9], None).unwrap();
+        assert!((y - 10_000_000.0).abs() < 0.001);
+        let [lon, lat] = utm_to_geo(0, [500_000.0, 10_000_000.0], 30, &Hemisphere::South).unwrap();
+        assert!((lon + 3.0).abs() < 1e-9 && lat.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_zone_edges() {
+        assert_eq!(zone_of([-6.0, 40.0]), (30, Hemisphere::North));
+        assert_eq!(zone_of([-6.000001, 40.0]).0, 29);
+        assert_eq!(zone_of([-180.0, 0.0]), (1, Hemisphere::North));
+        assert_eq!(zone_of([179.999, -0.001]), (60, Hemisphere::South));
+        // the same point in two neighbouring zones
+        let [x30, _] = geo_to_utm(0, [-6.0, 40.0], Some(30)).unwrap();
+        let [x29, _] = geo_to_utm(0, [-6.0, 40.0], Some(29)).unwrap();
+        assert!((x30 - 500_000.0 + (x29 - 500_000.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_norway_and_svalbard() {
+        // south-west Norway is in zone 32 instead of 31
+        assert_eq!(zone_of([5.32, 60.39]).0, 32);
+        assert_eq!(zone_of([2.9, 60.39]).0, 31);
+        assert_eq!(zone_of([5.32, 55.0]).0, 31);
+        assert_eq!(zone_of([5.32, 64.0]).0, 31);
+        assert_eq!(zone_of([3.0, 56.0]).0, 32);
+        // Svalbard has only the odd zones 31 to 37
+        assert_eq!(zone_of([8.9, 79.0]).0, 31);
+        assert_eq!(zone_of([15.65, 78.22]).0, 33);
+        assert_eq!(zone_of([25.0, 79.0]).0, 35);
+        assert_eq!(zone_of([33.0, 79.0]).0, 37);
+        assert_eq!(zone_of([42.0, 79.0]).0, 38);
+        assert_eq!(zone_of([15.65, 71.9]).0, 33);
+        assert_eq!(zone_of([20.0, 71.9]).0, 34);
+        assert!(mgrs([15.65, 78.22]).unwrap().starts_with("33X "));
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        assert!(geo_to_utm(0, [0.0, 84.0], None).is_ok());
+        assert!(geo_to_utm(0, [0.0, -80.0], None).is_ok());
+        assert!(matches!(
+            geo_to_utm(3, [0.0, 84.01], None),
+            Err(Error::OutOfRange {
+                point: 3,
+                coordinate: "Latitude",
+                ..
+            })
+        ));
+        assert!(geo_to_utm(0, [0.0, -80.01], None).is_err());
+        assert!(geo_to_utm(0, [180.0, 0.0], None).is_err());
+        assert!(matches!(
+            utm_to_geo(0, [99_999.0, 0.0], 30, &Hemisphere::North),
+            Err(Error::OutOfRange {
+                coordinate: "Easting",
+                ..
+            })
+        ));
+        assert!(matches!(
+            utm_to_geo(0, [500_000.0, 0.0], 61, &Hemisphere::North),
+            Err(Error::InvalidZone(61))
+        ));
+    }
+
+    #[test]
+    fn test_latitude_bands() {
+        assert_eq!(LatitudeBand::all().count(), 20);
+        assert_eq!(LatitudeBand::new('t'), Some(LatitudeBand('T')));
+        assert_eq!(LatitudeBand::new('I'), None);
+        assert_eq!(LatitudeBand::new('O'), None);
+        assert_eq!(LatitudeBand::of(41.65), Some(LatitudeBand('T')));
+        assert_eq!(LatitudeBand::of(-33.9), Some(LatitudeBand('H')));
+        assert_eq!(LatitudeBand::of(0.0), Some(LatitudeBand('N')));
+        assert_eq!(LatitudeBand::of(-0.1), Some(LatitudeBand('M')));
+        assert_eq!(LatitudeBand::of(-80.0), Some(LatitudeBand('C')));
+        assert_eq!(LatitudeBand::of(83.9), Some(LatitudeBand('X')));
+        assert_eq!(LatitudeBand::of(84.0), Some(LatitudeBand('X')));
+        assert_eq!(LatitudeBand::of(84.1), None);
+        assert_eq!(LatitudeBand('M').hemisphere(), Hemisphere::South);
+        assert_eq!(LatitudeBand('N').hemisphere(), Hemisphere::North);
+        assert_eq!(LatitudeBand('C').latitudes(), [-80.0, -72.0]);
+        assert_eq!(LatitudeBand('T').latitudes(), [40.0, 48.0]);
+        assert_eq!(LatitudeBand('X').latitudes(), [72.0, 84.0]);
+    }
+
+    #[test]
+    fn test_northings() {
+        assert_eq!(
+            Hemisphere::North.signed_northing(4_500_000.0),
+            Some(4_500_000.0)
+        );
+        assert_eq!(Hemisphere::North.signed_northing(-1.0), None);
+        assert_eq!(Hemisphere::North.signed_northing(9_500_000.0), None);
+        assert_eq!(
+            Hemisphere::South.signed_northing(6_000_000.0),
+            Some(-4_000_000.0)
+        );
+        assert_eq!(
+            Hemisphere::South.signed_northing(-4_000_000.0),
+            Some(-4_000_000.0)
+        );
+        assert_eq!(Hemisphere::South.signed_northing(1_000_000.0), None);
+        assert_eq!(Hemisphere::South.signed_northing(-9_000_000.0), None);
+        assert_eq!(Hemisphere::South.signed_northing(10_000_001.0), None);
+
+        // negative southern northings leave out the false northing
+        let cape_town = utm_to_geo(0, [261_000.0, 6_245_000.0], 34, &Hemisphere::South).unwrap();
+        let negative = utm_to_geo(0, [261_000.0, -3_755_000.0], 34, &Hemisphere::South).unwrap();
+        assert!((cape_town[0] - negative[0]).abs() < 1e-9);
+        assert!((cape_town[1] - negative[1]).abs() < 1e-9);
+        assert!(matches!(
+            utm_to_geo(1, [261_000.0, -3_755_000.0], 34, &Hemisphere::North),
+            Err(Error::OutOfRange {
+                point: 1,
+                coordinate: "Northing",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_utm_band_to_geo() {
+        let utm = geo_to_utm(0, [-0.87, 41.65], None).unwrap();
+        let [lon, lat] = utm_band_to_geo(0, utm, 30, LatitudeBand('T')).unwrap();
+        assert!((lon + 0.87).abs() < 1e-9 && (lat - 41.65).abs() < 1e-9);
+        assert!(matches!(
+            utm_band_to_geo(2, utm, 30, LatitudeBand('S')),
+            Err(Error::OutsideBand {
+                point: 2,
+                band: 'S',
+                ..
+            })
+        ));
+        // a band south of the equator reads the northing as a southern one
+        assert!(matches!(
+            utm_band_to_geo(0, utm, 30, LatitudeBand('H')),
+            Err(Error::OutsideBand { band: 'H', .. })
+        ));
+        let [_, lat] = utm_band_to_geo(0, [500_000.0, 0.0], 30, LatitudeBand('N')).unwrap();
+        assert!(lat.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_mgrs() {
+        // zone 30 takes its columns from S to Z and, being even, starts its rows at F
+        assert_eq!(mgrs([-3.0, 0.0]).as_deref(), Some("30N WF 00000 00000"));
+        // the Washington Monument, as published with the US National Grid
+        assert_eq!(
+            mgrs([-77.03524, 38.88947]).as_deref(),
+            Some("18S UJ 23483 06479")
+        );
+        // E44°24' N33°20', 38SMB4488 to the kilometre in the GeographicLib
+        // GeoConvert documentation
+        let reference = mgrs([44.4, 33.0 + 20.0 / 60.0]).unwrap();
+        assert!(reference.starts_with("38S MB 44"));
+        assert_eq!(&reference[13..15], "88");
+        assert_eq!(mgrs([0.0, 85.0]), None);
+    }
+
+    proptest! {
+        #[test]
+        fn geo_round_trip(lon in -180.0..180.0f64, lat in -80.0..=84.0f64) {
+            let (zone, hemisphere) = zone_of([lon, lat]);
+            let utm = geo_to_utm(0, [lon, lat], None).unwrap();
+            let back = utm_to_geo(0, utm, zone, &hemisphere).unwrap();
+            prop_assert!(distance(back, [lon, lat]) < 0.001);
+        }
+
+        #[test]
+        fn utm_round_trip(
+            x in 167_000.0..833_000.0f64,
+            // off the equator, where a northing of 10 000 km south is 0 north
+            y in 1.0..8_800_000.0f64,
+            zone in 1..=60u8,
+            north in any::<bool>(),
+        ) {
+            let (y, hemisphere) = if north {
+                (y, Hemisphere::North)
+            } else {
+                (10_000_000.0 - y, Hemisphere::South)
+            };
+            let geo = utm_to_geo(0, [x, y], zone, &hemisphere).unwrap();
+            // far from the central meridian of the first and last zones
+            prop_assume!((-180.0..180.0).contains(&geo[0]));
+            let [x2, y2] = geo_to_utm(0, geo, Some(zone)).unwrap();
+            prop_assert!((x2 - x).hypot(y2 - y) < 0.001);
+        }
+
+        #[test]
+        fn round_trip_in_neighbouring_zone(offset in -3.5..3.5f64, lat in -80.0..=84.0f64) {
+            // points just over a zone edge are often kept in the zone of the project
+            let lon = -3.0 + offset;
+            let utm = geo_to_utm(0, [lon, lat], Some(30)).unwrap();
+            let hemisphere = zone_of([lon, lat]).1;
+            let back = utm_to_geo(0, utm, 30, &hemisphere).unwrap();
+            prop_assert!(distance(back, [lon, lat]) < 0.001);
+        }
+    }
+}
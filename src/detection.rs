@@ -0,0 +1,310 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const EASTING_RANGE: std::ops::Range<f64> = 100_000.0..1_000_000.0;
+/// Southern northings may be given negative, without the false northing
+const NORTHING_RANGE: std::ops::RangeInclusive<f64> = -8_900_000.0..=10_000_000.0;
+/// Projected coordinates of places on Earth stay below this, in metres, even
+/// with large false eastings
+const MAX_PROJECTED: f64 = 100_000_000.0;
+
+/// Order of the two input columns, shared by geographic (lat/lon) and UTM
+/// (northing/easting) input.
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum AxisOrder {
+    Auto,
+    NorthFirst,
+    EastFirst,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Interpretation {
+    LatLon,
+    LonLat,
+    EastingNorthing,
+    NorthingEasting,
+    OtherProjected,
+}
+
+impl Interpretation {
+    pub fn is_geographic(&self) -> bool {
+        matches!(self, Interpretation::LatLon | Interpretation::LonLat)
+    }
+
+    pub fn axis_order(&self) -> AxisOrder {
+        match self {
+            Interpretation::LatLon | Interpretation::NorthingEasting => AxisOrder::NorthFirst,
+            Interpretation::LonLat | Interpretation::EastingNorthing => AxisOrder::EastFirst,
+            Interpretation::OtherProjected => AxisOrder::Auto,
+        }
+    }
+
+    /// Same coordinate system with the given axis order, `Auto` keeps the current one.
+    pub fn with_axis_order(self, order: AxisOrder) -> Interpretation {
+        match (self.is_geographic(), order) {
+            (_, AxisOrder::Auto) => self,
+            _ if self == Interpretation::OtherProjected => self,
+            (true, AxisOrder::NorthFirst) => Interpretation::LatLon,
+            (true, AxisOrder::EastFirst) => Interpretation::LonLat,
+            (false, AxisOrder::NorthFirst) => Interpretation::NorthingEasting,
+            (false, AxisOrder::EastFirst) => Interpretation::EastingNorthing,
+        }
+    }
+
+    pub fn swapped(self) -> Interpretation {
+        match self.axis_order() {
+            AxisOrder::NorthFirst => self.with_axis_order(AxisOrder::EastFirst),
+            AxisOrder::EastFirst => self.with_axis_order(AxisOrder::NorthFirst),
+            AxisOrder::Auto => self,
+        }
+    }
+}
+
+impl fmt::Display for Interpretation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interpretation::LatLon => write!(f, "Lat/Lon"),
+            Interpretation::LonLat => write!(f, "Lon/Lat"),
+            Interpretation::EastingNorthing => write!(f, "UTM Easting/Northing"),
+            Interpretation::NorthingEasting => write!(f, "UTM Northing/Easting"),
+            Interpretation::OtherProjected => write!(f, "Projected, not UTM"),
+        }
+    }
+}
+
+/// Candidate interpretations of a dataset, best first, with scores that add up
+/// to one. A dataset nothing fits has a single candidate with a score of zero.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Detection {
+    pub candidates: Vec<(Interpretation, f64)>,
+}
+
+impl Detection {
+    pub fn best(&self) -> Interpretation {
+        self.candidates[0].0
+    }
+
+    pub fn confidence(&self) -> f64 {
+        self.candidates[0].1
+    }
+
+    pub fn is_recognised(&self) -> bool {
+        self.confidence() > 0.0
+    }
+}
+
+fn fraction(rows: &[[f64; 3]], predicate: impl Fn(f64, f64) -> bool) -> f64 {
+    let n_matching = rows.iter().filter(|&&[a, b, _h]| predicate(a, b)).count();
+    n_matching as f64 / rows.len() as f64
+}
+
+fn is_utm(easting: f64, northing: f64) -> bool {
+    EASTING_RANGE.contains(&easting) && NORTHING_RANGE.contains(&northing)
+}
+
+fn mean(rows: &[[f64; 3]], column: usize) -> f64 {
+    rows.iter().map(|row| row[column]).sum::<f64>() / rows.len() as f64
+}
+
+/// Scores every interpretation against the whole dataset. `previous_region` is
+/// the [lon, lat] centre of the last dataset, used to break ties between axis orders.
+pub fn detect(rows: &[[f64; 3]], previous_region: Option<[f64; 2]>) -> Detection {
+    if rows.is_empty() {
+        return Detection {
+            candidates: vec![(Interpretation::LatLon, 1.0)],
+        };
+    }
+
+    let mut lat_lon = fraction(rows, |a, b| a.abs() <= 90.0 && b.abs() <= 180.0);
+    let mut lon_lat = fraction(rows, |a, b| a.abs() <= 180.0 && b.abs() <= 90.0);
+    let mut easting_northing = fraction(rows, is_utm);
+    let mut northing_easting = fraction(rows, |a, b| is_utm(b, a));
+    let other_projected = fraction(rows, |a, b| {
+        (180.0..MAX_PROJECTED).contains(&a.abs().max(b.abs())) && !is_utm(a, b) && !is_utm(b, a)
+    });
+
+    // Both axis orders fit the value ranges: prefer the one closer to where the
+    // previous dataset was, or the conventional order when there is none.
+    if lat_lon > 0.0 && lat_lon == lon_lat {
+        let (mean_a, mean_b) = (mean(rows, 0), mean(rows, 1));
+        match previous_region {
+            Some([lon, lat]) => {
+                let d_lat_lon = (mean_a - lat).hypot(mean_b - lon);
+                let d_lon_lat = (mean_a - lon).hypot(mean_b - lat);
+                if d_lat_lon <= d_lon_lat {
+                    lon_lat *= 0.5;
+                } else {
+                    lat_lon *= 0.5;
+                }
+            }
+            None => lon_lat *= 0.8,
+        }
+    }
+
+    // Northings are larger than eastings everywhere but close to the equator
+    if easting_northing > 0.0 && easting_northing == northing_easting {
+        if mean(rows, 1) >= mean(rows, 0) {
+            northing_easting *= 0.8;
+        } else {
+            easting_northing *= 0.8;
+        }
+    }
+
+    let mut candidates = vec![
+        (Interpretation::LatLon, lat_lon),
+        (Interpretation::LonLat, lon_lat),
+        (Interpretation::EastingNorthing, easting_northing),
+        (Interpretation::NorthingEasting, northing_easting),
+        (Interpretation::OtherProjected, other_projected),
+    ];
+
+    let total: f64 = candidates.iter().map(|(_i, score)| score).sum();
+    if total == 0.0 {
+        // not converted, as projected coordinates
+        return Detection {
+            candidates: vec![(Interpretation::OtherProjected, 0.0)],
+        };
+    }
+
+    candidates.retain(|(_i, score)| *score > 0.0);
+    for (_i, score) in candidates.iter_mut() {
+        *score /= total;
+    }
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    Detection { candidates }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interpretations(detection: &Detection) -> Vec<Interpretation> {
+        detection.candidates.iter().map(|(i, _score)| *i).collect()
+    }
+
+    #[test]
+    fn test_swapped() {
+        for interpretation in [
+            Interpretation::LatLon,
+            Interpretation::LonLat,
+            Interpretation::EastingNorthing,
+            Interpretation::NorthingEasting,
+        ] {
+            let swapped = interpretation.swapped();
+            assert_ne!(swapped, interpretation);
+            assert_eq!(swapped.is_geographic(), interpretation.is_geographic());
+            assert_eq!(swapped.swapped(), interpretation);
+        }
+        assert_eq!(
+            Interpretation::OtherProjected.swapped(),
+            Interpretation::OtherProjected
+        );
+    }
+
+    #[test]
+    fn test_with_axis_order() {
+        assert_eq!(
+            Interpretation::LonLat.with_axis_order(AxisOrder::NorthFirst),
+            Interpretation::LatLon
+        );
+        assert_eq!(
+            Interpretation::NorthingEasting.with_axis_order(AxisOrder::EastFirst),
+            Interpretation::EastingNorthing
+        );
+        assert_eq!(
+            Interpretation::LatLon.with_axis_order(AxisOrder::Auto),
+            Interpretation::LatLon
+        );
+        for order in [AxisOrder::Auto, AxisOrder::NorthFirst, AxisOrder::EastFirst] {
+            assert_eq!(
+                Interpretation::OtherProjected.with_axis_order(order),
+                Interpretation::OtherProjected
+            );
+        }
+    }
+
+    #[test]
+    fn test_lat_lon_and_lon_lat() {
+        // only one order fits the ranges
+        assert_eq!(
+            detect(&[[-0.87, 120.0, 0.0]], None).best(),
+            Interpretation::LatLon
+        );
+        assert_eq!(
+            detect(&[[120.0, -0.87, 0.0]], None).best(),
+            Interpretation::LonLat
+        );
+
+        // both orders fit: the conventional one, unless the previous dataset
+        // was closer to the other
+        let rows = [[41.65, -0.87, 0.0], [41.66, -0.88, 0.0]];
+        let detection = detect(&rows, None);
+        assert_eq!(
+            interpretations(&detection),
+            vec![Interpretation::LatLon, Interpretation::LonLat]
+        );
+        let scores: f64 = detection.candidates.iter().map(|(_i, score)| score).sum();
+        assert!((scores - 1.0).abs() < 1e-12);
+        assert_eq!(
+            detect(&rows, Some([-0.9, 41.6])).best(),
+            Interpretation::LatLon
+        );
+        assert_eq!(
+            detect(&rows, Some([41.6, -0.9])).best(),
+            Interpretation::LonLat
+        );
+    }
+
+    #[test]
+    fn test_easting_northing_and_northing_easting() {
+        assert_eq!(
+            interpretations(&detect(&[[676_000.0, 4_610_000.0, 0.0]], None)),
+            vec![Interpretation::EastingNorthing]
+        );
+        assert_eq!(
+            interpretations(&detect(&[[4_610_000.0, 676_000.0, 0.0]], None)),
+            vec![Interpretation::NorthingEasting]
+        );
+        // near the equator both orders fit, the larger column is the northing
+        let detection = detect(&[[300_000.0, 500_000.0, 0.0]], None);
+        assert_eq!(
+            interpretations(&detection),
+            vec![
+                Interpretation::EastingNorthing,
+                Interpretation::NorthingEasting
+            ]
+        );
+        assert!(detection.confidence() < 0.6);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        // projected, but not UTM
+        let detection = detect(&[[2_500_000.0, 1_200_000.0, 0.0]], None);
+        assert_eq!(detection.best(), Interpretation::OtherProjected);
+        assert_eq!(detection.confidence(), 1.0);
+        // no interpretation fits at all
+        for rows in [[[95.0, 95.0, 0.0]], [[1e9, 1e9, 0.0]]] {
+            let detection = detect(&rows, None);
+            assert_eq!(
+                interpretations(&detection),
+                vec![Interpretation::OtherProjected]
+            );
+            assert_eq!(detection.confidence(), 0.0);
+            assert!(!detection.is_recognised());
+        }
+        assert!(detect(&[[41.65, -0.87, 0.0]], None).is_recognised());
+        // a row out of range does not outvote the others
+        let mut rows = vec![[41.65, 120.0, 0.0]; 9];
+        rows.push([95.0, 95.0, 0.0]);
+        assert_eq!(detect(&rows, None).best(), Interpretation::LatLon);
+    }
+
+    #[test]
+    fn test_empty() {
+        let detection = detect(&[], Some([-0.87, 41.65]));
+        assert_eq!(interpretations(&detection), vec![Interpretation::LatLon]);
+        assert_eq!(detection.confidence(), 1.0);
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    Parse {
+        line: usize,
+        text: String,
+    },
+    OutOfRange {
+        point: usize,
+        coordinate: &'static str,
+        value: f64,
+    },
+    /// A UTM position outside the latitude band given with its zone
+    OutsideBand {
+        point: usize,
+        band: char,
+        latitude: f64,
+    },
+    InvalidZone(u8),
+    /// Projected coordinates that do not fit the UTM ranges
+    NotUtm,
+    NoData,
+    Io(String),
+    Clipboard(String),
+    Project(String),
+    Tiles(String),
+    Search(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse { line, text } => {
+                write!(
+                    f,
+                    "Line {}: could not read a coordinate pair from '{}'",
+                    line, text
+                )
+            }
+            Error::OutOfRange {
+                point,
+                coordinate,
+                value,
+            } => write!(
+                f,
+                "Point {}: {} {} is out of range",
+                point + 1,
+                coordinate,
+                value
+            ),
+            Error::OutsideBand {
+                point,
+                band,
+                latitude,
+            } => write!(
+                f,
+                "Point {}: latitude {:.5} is outside band {}",
+                point + 1,
+                latitude,
+                band
+            ),
+            Error::InvalidZone(zone) => write!(f, "Invalid UTM zone {}, must be 1 to 60", zone),
+            Error::NotUtm => write!(
+                f,
+                "The coordinates do not fit UTM ranges, they may be in another projected system. They were not converted"
+            ),
+            Error::NoData => write!(f, "No coordinates to convert"),
+            Error::Io(e) => write!(f, "File error: {}", e),
+            Error::Clipboard(e) => write!(f, "Clipboard error: {}", e),
+            Error::Project(e) => write!(f, "Invalid project file: {}", e),
+            Error::Tiles(e) => write!(f, "Tile source error: {}", e),
+            Error::Search(e) => write!(f, "Search error: {}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Project(e.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Tiles(e.to_string())
+    }
+}
@@ -2,16 +2,20 @@ use chrono::{NaiveDate, Utc};
 use std::fs::File;
 use std::io::Write;
 
-const EXPIRATION_DATE: &str = "2025-06-23";
+/// Set by the build script, three months after the build.
+const EXPIRATION_DATE: &str = env!("EXPIRATION_DATE");
 
-pub fn panic_if_expired() {
-    let expiration_date = NaiveDate::parse_from_str(EXPIRATION_DATE, "%Y-%m-%d").unwrap();
+fn expiration_date() -> NaiveDate {
+    NaiveDate::parse_from_str(EXPIRATION_DATE, "%Y-%m-%d").unwrap()
+}
+
+pub fn is_expired(today: NaiveDate) -> bool {
+    today > expiration_date()
+}
 
-    let now = Utc::now().date_naive();
-    if now > expiration_date {
-        let message = format!(
-            "The software has expired. Please contact the developer for an update: Javier Guallart <javier.guallart@dnv.com>"
-        );
+pub fn panic_if_expired() {
+    if is_expired(Utc::now().date_naive()) {
+        let message = "The software has expired. Please contact the developer for an update: Javier Guallart <javier.guallart@dnv.com>";
 
         let mut log = File::create("manual_data_cleaner_expired.log").unwrap();
         let _ = writeln!(log, "{}", message);
@@ -24,797 +28,16 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_chrono() {
-        panic_if_expired();
+    fn test_expiration_date() {
+        // the build script always sets a date months ahead of the build
+        assert!(expiration_date() > NaiveDate::from_ymd_opt(2025, 1, 1).unwrap());
     }
-}
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
-
 
+    #[test]
+    fn test_is_expired() {
+        let date = expiration_date();
+        assert!(!is_expired(date - chrono::Days::new(30)));
+        assert!(!is_expired(date));
+        assert!(is_expired(date + chrono::Days::new(1)));
+    }
+}
@@ -0,0 +1,593 @@
+use crate::conversion::Point;
+use crate::layer::{Geometry, Layer};
+use crate::settings::Settings;
+use itertools::Itertools;
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The layers an export or copy covers and how it writes their points.
+pub struct Export<'a> {
+    pub layers: Vec<&'a Layer>,
+    /// Rows start with the layer name, when exporting several layers
+    pub layer_column: bool,
+    pub exclude_invalid: bool,
+    pub settings: &'a Settings,
+}
+
+impl<'a> Export<'a> {
+    /// Exported points with their layer and index in it.
+    pub fn points(&self) -> impl Iterator<Item = (&'a Layer, usize, &'a Point)> + '_ {
+        self.layers
+            .iter()
+            .flat_map(|&layer| {
+                layer
+                    .points
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, p)| (layer, i, p))
+            })
+            .filter(|(_layer, _i, p)| !self.exclude_invalid || p.is_valid())
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = (&'a Layer, usize)> + '_ {
+        self.layers
+            .iter()
+            .flat_map(|&layer| (0..layer.points.len()).map(move |i| (layer, i)))
+    }
+
+    fn layer_column(&self, layer: &Layer) -> String {
+        if self.layer_column {
+            format!("{}\t", layer.name)
+        } else {
+            String::new()
+        }
+    }
+
+    fn layer_header(&self) -> &'static str {
+        if self.layer_column { "Layer\t" } else { "" }
+    }
+
+    /// Latitude and longitude, a point per line.
+    pub fn geo_text(&self) -> String {
+        self.points()
+            .map(|(layer, _i, p)| self.layer_column(layer) + &self.settings.format_geo(p.geo))
+            .join("\n")
+    }
+
+    /// Easting and northing, a point per line.
+    pub fn utm_text(&self) -> String {
+        self.points()
+            .map(|(layer, _i, p)| self.layer_column(layer) + &self.settings.format_utm(p.utm))
+            .join("\n")
+    }
+
+    pub fn csv_utm(&self) -> String {
+        let mut csv = format!("{}Easting\tNorthing\n", self.layer_header());
+        for (layer, _i, point) in self.points() {
+            csv += &format!(
+                "{}{}\n",
+                self.layer_column(layer),
+                self.settings.format_utm(point.utm)
+            );
+        }
+        csv
+    }
+
+    pub fn csv_latlon(&self) -> String {
+        let mut csv = format!("{}Latitude\tLongitude\n", self.layer_header());
+        for (layer, _i, point) in self.points() {
+            csv += &format!(
+                "{}{}\n",
+                self.layer_column(layer),
+                self.settings.format_geo(point.geo)
+            );
+        }
+        csv
+    }
+
+    /// Both systems side by side, with the zone and height of each point.
+    pub fn combined_table(&self, rows: impl Iterator<Item = (&'a Layer, usize)>) -> String {
+        let header = format!(
+            "{}Id\tLatitude\tLongitude\tZone\tHemisphere\tEasting\tNorthing\tHeight",
+            self.layer_header()
+        );
+        let rows = rows
+            .map(|(layer, i)| (layer, i, &layer.points[i]))
+            .filter(|(_layer, _i, p)| !self.exclude_invalid || p.is_valid())
+            .map(|(layer, i, point)| {
+                let (zone, hemisphere) = match layer.zone_of_point(point) {
+                    Some((zone, hemisphere)) => (zone.to_string(), hemisphere.to_string()),
+                    None => (String::new(), String::new()),
+                };
+                format!(
+                    "{}{}\t{}\t{}\t{}\t{}\t{}",
+                    self.layer_column(layer),
+                    i + 1,
+                    self.settings.format_geo(point.geo),
+                    zone,
+                    hemisphere,
+                    self.settings.format_utm(point.utm),
+                    self.settings.format_height(point.height)
+                )
+            });
+
+        std::iter::once(header).chain(rows).join("\n")
+    }
+
+    /// One folder per layer, with the layer colour on its placemarks.
+    pub fn kml(&self) -> String {
+        let folders = self
+            .layers
+            .iter()
+            .enumerate()
+            .map(|(l, layer)| {
+                let coordinates: Vec<String> = layer
+                    .valid_geo_coords()
+                    .map(|[lon, lat]| format!("{},{},0", lon, lat))
+                    .collect();
+                let geometry = match layer.geometry {
+                    Geometry::Points => String::new(),
+                    Geometry::Line => format!(
+                        r#"<Placemark>
+                            <name>{}</name>
+                            <styleUrl>#layer{}</styleUrl>
+                            <LineString>
+                                <coordinates>{}</coordinates>
+                            </LineString>
+                        </Placemark>"#,
+                        xml_escape(&layer.name),
+                        l,
+                        coordinates.join(" ")
+                    ),
+                    Geometry::Polygon => format!(
+                        r#"<Placemark>
+                            <name>{}</name>
+                            <styleUrl>#layer{}</styleUrl>
+                            <Polygon>
+                                <outerBoundaryIs>
+                                    <LinearRing>
+                                        <coordinates>{}</coordinates>
+                                    </LinearRing>
+                                </outerBoundaryIs>
+                            </Polygon>
+                        </Placemark>"#,
+                        xml_escape(&layer.name),
+                        l,
+                        // the ring is closed on its first vertex
+                        coordinates.iter().chain(coordinates.first()).join(" ")
+                    ),
+                };
+                format!(
+                    r#"<Style id="layer{}">
+                        <IconStyle>
+                            <color>{}</color>
+                        </IconStyle>
+                        <LineStyle>
+                            <color>{}</color>
+                            <width>2</width>
+                        </LineStyle>
+                        <PolyStyle>
+                            <color>40{}</color>
+                        </PolyStyle>
+                    </Style>
+                    <Folder>
+                        <name>{}</name>
+                        {}
+                        {}
+                    </Folder>"#,
+                    l,
+                    layer.kml_color(),
+                    layer.kml_color(),
+                    // translucent fill
+                    &layer.kml_color()[2..],
+                    xml_escape(&layer.name),
+                    geometry,
+                    coordinates
+                        .iter()
+                        .map(|coordinates| format!(
+                            r#"<Placemark>
+                            <styleUrl>#layer{}</styleUrl>
+                            <Point>
+                                <coordinates>{}</coordinates>
+                            </Point>
+                        </Placemark>"#,
+                            l, coordinates
+                        ))
+                        .join("\n")
+                )
+            })
+            .join("\n");
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <kml xmlns="http://www.opengis.net/kml/2.2">
+                <Document>
+                    <name>Coordinates</name>
+                    {}
+                </Document>
+            </kml>"#,
+            folders
+        )
+    }
+
+    /// A FeatureCollection with the points of every exported layer, and its
+    /// line or polygon.
+    pub fn geojson(&self) -> serde_json::Value {
+        let mut features = vec![];
+        for layer in &self.layers {
+            let mut coordinates: Vec<[f64; 2]> = layer.valid_geo_coords().collect();
+            let geometry = match layer.geometry {
+                Geometry::Points => None,
+                Geometry::Line if coordinates.len() >= 2 => Some(serde_json::json!({
+                    "type": "LineString",
+                    "coordinates": coordinates,
+                })),
+                Geometry::Polygon if coordinates.len() >= 3 => {
+                    // RFC 7946 wants the exterior ring counterclockwise and closed
+                    let signed_area: f64 = coordinates
+                        .iter()
+                        .zip(coordinates.iter().cycle().skip(1))
+                        .map(|([x1, y1], [x2, y2])| x1 * y2 - x2 * y1)
+                        .sum();
+                    if signed_area < 0.0 {
+                        coordinates.reverse();
+                    }
+                    coordinates.push(coordinates[0]);
+                    Some(serde_json::json!({
+                        "type": "Polygon",
+                        "coordinates": [coordinates],
+                    }))
+                }
+                Geometry::Line | Geometry::Polygon => None,
+            };
+            if let Some(geometry) = geometry {
+                features.push(serde_json::json!({
+                    "type": "Feature",
+                    "geometry": geometry,
+                    "properties": { "layer": layer.name },
+                }));
+            }
+            // numbered by table row, as in the CSV exports
+            for (i, point) in layer.points.iter().enumerate() {
+                let (Some([lon, lat]), true) = (point.geo, point.is_valid()) else {
+                    continue;
+                };
+                features.push(serde_json::json!({
+                    "type": "Feature",
+                    "geometry": { "type": "Point", "coordinates": [lon, lat, point.height] },
+                    "properties": { "layer": layer.name, "id": i + 1 },
+                }));
+            }
+        }
+        serde_json::json!({
+            "type": "FeatureCollection",
+            "features": features,
+        })
+    }
+
+    /// An R12 ASCII DXF in UTM coordinates, one DXF layer per exported layer:
+    /// POINTs, and a 3D POLYLINE through them for lines and polygons.
+    pub fn dxf(&self) -> String {
+        let mut dxf = String::from("0\nSECTION\n2\nENTITIES\n");
+        for layer in &self.layers {
+            // layer names are limited to letters, digits, '-' and '_'
+            let name: String = layer
+                .name
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            let vertices: Vec<[f64; 3]> = layer
+                .valid_points()
+                .filter_map(|p| p.utm.map(|[x, y]| [x, y, p.height]))
+                .collect();
+            for [x, y, z] in &vertices {
+                dxf += &format!("0\nPOINT\n8\n{}\n10\n{}\n20\n{}\n30\n{}\n", name, x, y, z);
+            }
+            if layer.geometry != Geometry::Points && vertices.len() >= 2 {
+                let closed = if layer.geometry == Geometry::Polygon {
+                    9
+                } else {
+                    8
+                };
+                dxf += &format!("0\nPOLYLINE\n8\n{}\n66\n1\n70\n{}\n", name, closed);
+                for [x, y, z] in &vertices {
+                    dxf += &format!(
+                        "0\nVERTEX\n8\n{}\n10\n{}\n20\n{}\n30\n{}\n70\n32\n",
+                        name, x, y, z
+                    );
+                }
+                dxf += &format!("0\nSEQEND\n8\n{}\n", name);
+            }
+        }
+        dxf += "0\nENDSEC\n0\nEOF\n";
+        dxf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversion::{ConversionMode, Hemisphere};
+    use crate::error::Error;
+    use crate::layer::LAYER_COLORS;
+
+    /// Converted layers with fixed coordinates, so that the snapshots only
+    /// depend on the exporters.
+    fn layers() -> [Layer; 2] {
+        let point = |geo: [f64; 2], utm: [f64; 2], height: f64| Point {
+            geo: Some(geo),
+            utm: Some(utm),
+            height,
+            error: None,
+        };
+        let mut points = Layer::new("Points & marks".to_string(), LAYER_COLORS[0]);
+        points.points = vec![
+            point([-0.87, 41.65], [677369.72, 4613108.9], 200.0),
+            point([-0.88, 41.66], [676509.63, 4614198.68], 210.5),
+            Point {
+                error: Some(Error::OutOfRange {
+                    point: 2,
+                    coordinate: "Latitude",
+                    value: 95.0,
+                }),
+                ..Point::from_geo(0.0, 95.0, 0.0)
+            },
+        ];
+        let mut line = Layer::new("Line".to_string(), LAYER_COLORS[1]);
+        line.conversion_mode = ConversionMode::UTMtoLatLon;
+        line.utm_zone = 56;
+        line.hemisphere = Hemisphere::South;
+        line.geometry = Geometry::Line;
+        line.points = vec![
+            point([151.205137, -33.877291], [334000.0, 6250000.0], 0.0),
+            point([151.216133, -33.868433], [335000.0, 6251000.0], 1.0),
+        ];
+        [points, line]
+    }
+
+    fn export<'a>(layers: &'a [Layer], settings: &'a Settings) -> Export<'a> {
+        Export {
+            layers: layers.iter().collect(),
+            layer_column: true,
+            exclude_invalid: true,
+            settings,
+        }
+    }
+
+    #[test]
+    fn test_csv() {
+        let layers = layers();
+        let settings = Settings::default();
+        let export = export(&layers, &settings);
+        assert_eq!(
+            export.csv_utm(),
+            "Layer\tEasting\tNorthing
+Points & marks\t677369.72\t4613108.90
+Points & marks\t676509.63\t4614198.68
+Line\t334000.00\t6250000.00
+Line\t335000.00\t6251000.00
+"
+        );
+        assert_eq!(
+            export.csv_latlon(),
+            "Layer\tLatitude\tLongitude
+Points & marks\t41.650000\t-0.870000
+Points & marks\t41.660000\t-0.880000
+Line\t-33.877291\t151.205137
+Line\t-33.868433\t151.216133
+"
+        );
+        assert_eq!(
+            export.combined_table(export.rows()),
+            "Layer\tId\tLatitude\tLongitude\tZone\tHemisphere\tEasting\tNorthing\tHeight
+Points & marks\t1\t41.650000\t-0.870000\t30\tNorth\t677369.72\t4613108.90\t200.00
+Points & marks\t2\t41.660000\t-0.880000\t30\tNorth\t676509.63\t4614198.68\t210.50
+Line\t1\t-33.877291\t151.205137\t56\tSouth\t334000.00\t6250000.00\t0.00
+Line\t2\t-33.868433\t151.216133\t56\tSouth\t335000.00\t6251000.00\t1.00"
+        );
+    }
+
+    #[test]
+    fn test_csv_active_layer_with_comma() {
+        let layers = layers();
+        let settings = Settings {
+            decimal_separator: crate::settings::DecimalSeparator::Comma,
+            utm_decimals: 1,
+            ..Settings::default()
+        };
+        let export = Export {
+            layers: vec![&layers[0]],
+            layer_column: false,
+            exclude_invalid: false,
+            settings: &settings,
+        };
+        assert_eq!(
+            export.csv_utm(),
+            "Easting\tNorthing\n677369,7\t4613108,9\n676509,6\t4614198,7\n\t\n"
+        );
+    }
+
+    #[test]
+    fn test_kml() {
+        let layers = layers();
+        let settings = Settings::default();
+        let kml = export(&layers, &settings).kml();
+        let lines: Vec<&str> = kml.lines().map(str::trim).collect();
+        assert_eq!(
+            lines.join("\n"),
+            r##"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2">
+<Document>
+<name>Coordinates</name>
+<Style id="layer0">
+<IconStyle>
+<color>ff0000ff</color>
+</IconStyle>
+<LineStyle>
+<color>ff0000ff</color>
+<width>2</width>
+</LineStyle>
+<PolyStyle>
+<color>400000ff</color>
+</PolyStyle>
+</Style>
+<Folder>
+<name>Points &amp; marks</name>
+
+<Placemark>
+<styleUrl>#layer0</styleUrl>
+<Point>
+<coordinates>-0.87,41.65,0</coordinates>
+</Point>
+</Placemark>
+<Placemark>
+<styleUrl>#layer0</styleUrl>
+<Point>
+<coordinates>-0.88,41.66,0</coordinates>
+</Point>
+</Placemark>
+</Folder>
+<Style id="layer1">
+<IconStyle>
+<color>ffff781e</color>
+</IconStyle>
+<LineStyle>
+<color>ffff781e</color>
+<width>2</width>
+</LineStyle>
+<PolyStyle>
+<color>40ff781e</color>
+</PolyStyle>
+</Style>
+<Folder>
+<name>Line</name>
+<Placemark>
+<name>Line</name>
+<styleUrl>#layer1</styleUrl>
+<LineString>
+<coordinates>151.205137,-33.877291,0 151.216133,-33.868433,0</coordinates>
+</LineString>
+</Placemark>
+<Placemark>
+<styleUrl>#layer1</styleUrl>
+<Point>
+<coordinates>151.205137,-33.877291,0</coordinates>
+</Point>
+</Placemark>
+<Placemark>
+<styleUrl>#layer1</styleUrl>
+<Point>
+<coordinates>151.216133,-33.868433,0</coordinates>
+</Point>
+</Placemark>
+</Folder>
+</Document>
+</kml>"##
+        );
+    }
+
+    #[test]
+    fn test_dxf() {
+        let layers = layers();
+        let settings = Settings::default();
+        let dxf = export(&layers, &settings).dxf();
+        // group codes and values, an entity per line
+        let expected = "0 SECTION 2 ENTITIES
+            0 POINT 8 Points___marks 10 677369.72 20 4613108.9 30 200
+            0 POINT 8 Points___marks 10 676509.63 20 4614198.68 30 210.5
+            0 POINT 8 Line 10 334000 20 6250000 30 0
+            0 POINT 8 Line 10 335000 20 6251000 30 1
+            0 POLYLINE 8 Line 66 1 70 8
+            0 VERTEX 8 Line 10 334000 20 6250000 30 0 70 32
+            0 VERTEX 8 Line 10 335000 20 6251000 30 1 70 32
+            0 SEQEND 8 Line
+            0 ENDSEC 0 EOF";
+        assert_eq!(dxf, expected.split_whitespace().join("\n") + "\n");
+    }
+
+    #[test]
+    fn test_geojson() {
+        let layers = layers();
+        let settings = Settings::default();
+        let geojson = export(&layers, &settings).geojson();
+        let point = |layer: &str, id: usize, coordinates: [f64; 3]| {
+            serde_json::json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": coordinates },
+                "properties": { "layer": layer, "id": id },
+            })
+        };
+        assert_eq!(
+            geojson,
+            serde_json::json!({
+                "type": "FeatureCollection",
+                "features": [
+                    point("Points & marks", 1, [-0.87, 41.65, 200.0]),
+                    point("Points & marks", 2, [-0.88, 41.66, 210.5]),
+                    {
+                        "type": "Feature",
+                        "geometry": {
+                            "type": "LineString",
+                            "coordinates": [[151.205137, -33.877291], [151.216133, -33.868433]],
+                        },
+                        "properties": { "layer": "Line" },
+                    },
+                    point("Line", 1, [151.205137, -33.877291, 0.0]),
+                    point("Line", 2, [151.216133, -33.868433, 1.0]),
+                ],
+            })
+        );
+
+        // the ids stay those of the table after an invalid point
+        let mut layers = layers;
+        layers[0].points.swap(0, 2);
+        let geojson = export(&layers, &settings).geojson();
+        assert_eq!(geojson["features"][0]["properties"]["id"], 2);
+        assert_eq!(geojson["features"][1]["properties"]["id"], 3);
+    }
+
+    #[test]
+    fn test_polygon() {
+        let mut layer = Layer::new("Plot".to_string(), LAYER_COLORS[2]);
+        layer.geometry = Geometry::Polygon;
+        // clockwise
+        layer.points = [[0.0, 0.0], [0.0, 1.0], [1.0, 1.0]]
+            .map(|[lon, lat]| Point {
+                utm: Some([500_000.0 + lon, lat]),
+                ..Point::from_geo(lon, lat, 0.0)
+            })
+            .to_vec();
+        let settings = Settings::default();
+        let export = Export {
+            layers: vec![&layer],
+            layer_column: false,
+            exclude_invalid: false,
+            settings: &settings,
+        };
+        // counterclockwise and closed
+        assert_eq!(
+            export.geojson()["features"][0]["geometry"]["coordinates"],
+            serde_json::json!([[[1.0, 1.0], [0.0, 1.0], [0.0, 0.0], [1.0, 1.0]]])
+        );
+        assert!(
+            export
+                .kml()
+                .contains("<coordinates>0,0,0 0,1,0 1,1,0 0,0,0</coordinates>")
+        );
+        assert!(
+            export
+                .dxf()
+                .contains("0\nPOLYLINE\n8\nPlot\n66\n1\n70\n9\n")
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use egui::{Color32, RichText, ScrollArea};
+
+const MAX_ENTRIES: usize = 100;
+
+struct Entry<T> {
+    label: String,
+    state: T,
+}
+
+pub enum Jump {
+    Undo(usize),
+    Redo(usize),
+}
+
+/// Undo and redo stacks. Each entry holds the state from before the change
+/// named by its label.
+pub struct History<T> {
+    undo: Vec<Entry<T>>,
+    redo: Vec<Entry<T>>,
+    revision: u64,
+    pub window_open: bool,
+}
+
+impl<T> Default for History<T> {
+    fn default() -> Self {
+        History {
+            undo: vec![],
+            redo: vec![],
+            revision: 0,
+            window_open: false,
+        }
+    }
+}
+
+impl<T> History<T> {
+    pub fn record(&mut self, label: impl Into<String>, state: T) {
+        self.revision += 1;
+        self.redo.clear();
+        self.undo.push(Entry {
+            label: label.into(),
+            state,
+        });
+        if self.undo.len() > MAX_ENTRIES {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Like `record`, but merges into the last entry when it has the same label,
+    /// so that a continuous edit (e.g. dragging a value) is undone in one step.
+    pub fn record_merged(&mut self, label: impl Into<String>, state: T) {
+        let label = label.into();
+        let same_as_last = self.undo.last().is_some_and(|entry| entry.label == label);
+        if !same_as_last || !self.redo.is_empty() {
+            self.record(label, state);
+        } else {
+            self.revision += 1;
+        }
+    }
+
+    /// Changes every time the state is changed, undone or redone.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn undo(&mut self, current: T) -> Option<(String, T)> {
+        let entry = self.undo.pop()?;
+        self.revision += 1;
+        self.redo.push(Entry {
+            label: entry.label.clone(),
+            state: current,
+        });
+        Some((entry.label, entry.state))
+    }
+
+    pub fn redo(&mut self, current: T) -> Option<(String, T)> {
+        let entry = self.redo.pop()?;
+        self.revision += 1;
+        self.undo.push(Entry {
+            label: entry.label.clone(),
+            state: current,
+        });
+        Some((entry.label, entry.state))
+    }
+
+    /// Lists the changes, oldest first. Clicking one returns how many steps to
+    /// undo or redo to get back to the state right after it.
+    pub fn show_window(&mut self, ctx: &egui::Context) -> Option<Jump> {
+        let mut jump = None;
+        let n_undo = self.undo.len();
+        egui::Window::new("History")
+            .open(&mut self.window_open)
+            .default_size([300.0, 300.0])
+            .show(ctx, |ui| {
+                ScrollArea::vertical().show(ui, |ui| {
+                    if ui.selectable_label(n_undo == 0, "Initial state").clicked() {
+                        jump = Some(Jump::Undo(n_undo));
+                    }
+                    for (i, entry) in self.undo.iter().enumerate() {
+                        let current = i + 1 == n_undo;
+                        if ui.selectable_label(current, &entry.label).clicked() {
+                            jump = Some(Jump::Undo(n_undo - i - 1));
+                        }
+                    }
+                    for (i, entry) in self.redo.iter().rev().enumerate() {
+                        let text = RichText::new(&entry.label).color(Color32::GRAY);
+                        if ui.selectable_label(false, text).clicked() {
+                            jump = Some(Jump::Redo(i + 1));
+                        }
+                    }
+                });
+            });
+        jump
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_redo() {
+        let mut history = History::default();
+        assert!(!history.can_undo() && !history.can_redo());
+        history.record("First", 0);
+        history.record("Second", 1);
+        let revision = history.revision();
+
+        assert_eq!(history.undo(2), Some(("Second".to_string(), 1)));
+        assert_eq!(history.undo(1), Some(("First".to_string(), 0)));
+        assert_eq!(history.undo(0), None);
+        assert!(history.revision() > revision);
+
+        assert_eq!(history.redo(0), Some(("First".to_string(), 1)));
+        assert!(history.can_redo());
+        // a new change drops what could be redone
+        history.record("Third", 1);
+        assert!(!history.can_redo());
+        assert_eq!(history.redo(3), None);
+        assert_eq!(history.undo(3), Some(("Third".to_string(), 1)));
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let mut history = History::default();
+        for i in 0..MAX_ENTRIES + 10 {
+            history.record(format!("Change {}", i), i);
+        }
+        let mut undone = vec![];
+        while let Some((_label, state)) = history.undo(0) {
+            undone.push(state);
+        }
+        // the oldest changes are dropped first
+        assert_eq!(undone.len(), MAX_ENTRIES);
+        assert_eq!(undone.last(), Some(&10));
+    }
+
+    #[test]
+    fn test_record_merged() {
+        let mut history = History::default();
+        history.record_merged("Change UTM zone", 30);
+        let revision = history.revision();
+        history.record_merged("Change UTM zone", 31);
+        history.record_merged("Change UTM zone", 32);
+        // still a change for autosave and the title, but one undo step
+        assert!(history.revision() > revision);
+        assert_eq!(history.undo(33), Some(("Change UTM zone".to_string(), 30)));
+        assert!(!history.can_undo());
+
+        // another label starts a new entry
+        history.record_merged("Change UTM zone", 30);
+        history.record_merged("Hemisphere South", 31);
+        history.record_merged("Change UTM zone", 32);
+        assert_eq!(history.undo(33).map(|(_label, state)| state), Some(32));
+        assert_eq!(history.undo(32).map(|(_label, state)| state), Some(31));
+
+        // and so does a change after an undo
+        assert_eq!(history.undo(31).map(|(_label, state)| state), Some(30));
+        history.record("Change UTM zone", 30);
+        history.record("Hemisphere South", 31);
+        history.undo(32);
+        history.record_merged("Change UTM zone", 31);
+        assert_eq!(history.undo(33).map(|(_label, state)| state), Some(31));
+        assert_eq!(history.undo(31).map(|(_label, state)| state), Some(30));
+    }
+}
@@ -0,0 +1,78 @@
+use egui::{Color32, FontId, Painter, Pos2, Rect, vec2};
+use std::collections::HashMap;
+
+/// Side of the cells of the spatial index, in points.
+const CELL: f32 = 64.0;
+/// Most labels drawn in one frame, beyond that the map is too dense to read them.
+pub const MAX_LABELS: usize = 2000;
+
+/// Places labels next to their markers without overlapping each other or
+/// other markers. A label that fits nowhere is left out.
+#[derive(Default)]
+pub struct LabelPlacer {
+    cells: HashMap<(i32, i32), Vec<Rect>>,
+    placed: usize,
+}
+
+impl LabelPlacer {
+    fn cells_of(rect: Rect) -> impl Iterator<Item = (i32, i32)> {
+        let [x0, y0] = [
+            (rect.min.x / CELL).floor() as i32,
+            (rect.min.y / CELL).floor() as i32,
+        ];
+        let [x1, y1] = [
+            (rect.max.x / CELL).floor() as i32,
+            (rect.max.y / CELL).floor() as i32,
+        ];
+        (x0..=x1).flat_map(move |x| (y0..=y1).map(move |y| (x, y)))
+    }
+
+    fn is_free(&self, rect: Rect) -> bool {
+        Self::cells_of(rect).all(|cell| {
+            self.cells
+                .get(&cell)
+                .is_none_or(|rects| rects.iter().all(|other| !other.intersects(rect)))
+        })
+    }
+
+    /// Reserves the area of something labels must not cover, such as a marker.
+    pub fn block(&mut self, rect: Rect) {
+        for cell in Self::cells_of(rect) {
+            self.cells.entry(cell).or_default().push(rect);
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.placed >= MAX_LABELS
+    }
+
+    /// Draws `text` at the first free place around the marker of this
+    /// `radius` at `center`: right, left, above or below it.
+    pub fn place(&mut self, painter: &Painter, center: Pos2, radius: f32, text: String) {
+        if self.is_full() {
+            return;
+        }
+        let galley = painter.layout_no_wrap(text, FontId::proportional(11.0), Color32::BLACK);
+        let size = galley.size() + vec2(4.0, 2.0);
+        let gap = radius + 2.0;
+        let candidates = [
+            Rect::from_min_size(center + vec2(gap, -gap - size.y + 4.0), size),
+            Rect::from_min_size(center + vec2(gap, gap - 4.0), size),
+            Rect::from_min_size(center + vec2(-gap - size.x, -gap - size.y + 4.0), size),
+            Rect::from_min_size(center + vec2(-gap - size.x, gap - 4.0), size),
+            Rect::from_min_size(center + vec2(-size.x / 2.0, -gap - size.y), size),
+            Rect::from_min_size(center + vec2(-size.x / 2.0, gap), size),
+        ];
+        let clip = painter.clip_rect();
+        let Some(rect) = candidates
+            .into_iter()
+            .find(|rect| clip.contains_rect(*rect) && self.is_free(*rect))
+        else {
+            return;
+        };
+        self.block(rect);
+        self.placed += 1;
+        painter.rect_filled(rect, 2.0, Color32::from_white_alpha(170));
+        painter.galley(rect.min + vec2(2.0, 1.0), galley, Color32::BLACK);
+    }
+}
@@ -0,0 +1,854 @@
+use crate::conversion::{
+    ConversionMode, Hemisphere, LatitudeBand, Point, geo_to_utm, utm_band_to_geo, utm_to_geo,
+    zone_of,
+};
+use crate::detection::{AxisOrder, Detection, Interpretation};
+use crate::error::Error;
+use crate::settings::Settings;
+use egui::{Color32, ComboBox, DragValue, Grid, Painter, Pos2, Rect, Shape, Stroke, vec2};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Layers with at least this many points are converted on all cores.
+const PARALLEL_POINTS: usize = 5_000;
+
+/// Colours given to new layers, in turn.
+pub const LAYER_COLORS: [Color32; 6] = [
+    Color32::RED,
+    Color32::from_rgb(30, 120, 255),
+    Color32::from_rgb(40, 180, 60),
+    Color32::from_rgb(255, 140, 0),
+    Color32::from_rgb(160, 60, 200),
+    Color32::from_rgb(0, 170, 170),
+];
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum MarkerStyle {
+    Circle,
+    Square,
+    Triangle,
+    Diamond,
+}
+
+impl MarkerStyle {
+    pub const ALL: [MarkerStyle; 4] = [
+        MarkerStyle::Circle,
+        MarkerStyle::Square,
+        MarkerStyle::Triangle,
+        MarkerStyle::Diamond,
+    ];
+
+    pub fn paint(&self, painter: &Painter, center: Pos2, radius: f32, fill: Color32) {
+        let stroke = Stroke::new(1.0, Color32::BLACK);
+        let polygon = |corners: &[[f32; 2]]| {
+            let points = corners
+                .iter()
+                .map(|[x, y]| center + radius * vec2(*x, *y))
+                .collect();
+            Shape::convex_polygon(points, fill, stroke)
+        };
+        match self {
+            MarkerStyle::Circle => {
+                painter.circle(center, radius, fill, stroke);
+            }
+            MarkerStyle::Square => {
+                painter.add(polygon(&[
+                    [-0.9, -0.9],
+                    [0.9, -0.9],
+                    [0.9, 0.9],
+                    [-0.9, 0.9],
+                ]));
+            }
+            MarkerStyle::Triangle => {
+                painter.add(polygon(&[[0.0, -1.2], [1.1, 0.8], [-1.1, 0.8]]));
+            }
+            MarkerStyle::Diamond => {
+                painter.add(polygon(&[[0.0, -1.2], [1.2, 0.0], [0.0, 1.2], [-1.2, 0.0]]));
+            }
+        }
+    }
+}
+
+/// A cluster of `count` markers, sized by its count; returns its radius.
+pub fn paint_cluster(painter: &Painter, center: Pos2, count: usize, color: Color32) -> f32 {
+    let radius = 9.0 + 3.0 * (count as f32).log10();
+    painter.circle(
+        center,
+        radius,
+        color.gamma_multiply(0.75),
+        Stroke::new(1.5, Color32::WHITE),
+    );
+    painter.text(
+        center,
+        egui::Align2::CENTER_CENTER,
+        count.to_string(),
+        egui::FontId::proportional(11.0),
+        Color32::WHITE,
+    );
+    radius
+}
+
+impl fmt::Display for MarkerStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarkerStyle::Circle => write!(f, "Circle"),
+            MarkerStyle::Square => write!(f, "Square"),
+            MarkerStyle::Triangle => write!(f, "Triangle"),
+            MarkerStyle::Diamond => write!(f, "Diamond"),
+        }
+    }
+}
+
+/// What the marker fill colour shows.
+#[derive(PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum ColorBy {
+    /// The layer colour
+    #[default]
+    Layer,
+    /// A blue to red ramp from the lowest to the highest point
+    Height,
+    /// One colour per UTM zone
+    Zone,
+}
+
+impl ColorBy {
+    pub const ALL: [ColorBy; 3] = [ColorBy::Layer, ColorBy::Height, ColorBy::Zone];
+}
+
+impl fmt::Display for ColorBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorBy::Layer => write!(f, "Layer colour"),
+            ColorBy::Height => write!(f, "Height"),
+            ColorBy::Zone => write!(f, "UTM zone"),
+        }
+    }
+}
+
+/// Text shown next to each marker.
+#[derive(PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum PointLabel {
+    #[default]
+    None,
+    /// Row number in the table
+    Number,
+    Height,
+}
+
+impl PointLabel {
+    pub const ALL: [PointLabel; 3] = [PointLabel::None, PointLabel::Number, PointLabel::Height];
+}
+
+impl fmt::Display for PointLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PointLabel::None => write!(f, "None"),
+            PointLabel::Number => write!(f, "Point number"),
+            PointLabel::Height => write!(f, "Height"),
+        }
+    }
+}
+
+/// Blue, green, yellow, red along `t` in 0..=1.
+fn ramp(t: f32) -> Color32 {
+    const STOPS: [[f32; 3]; 4] = [
+        [40.0, 80.0, 230.0],
+        [40.0, 190.0, 80.0],
+        [240.0, 210.0, 40.0],
+        [220.0, 40.0, 30.0],
+    ];
+    let t = t.clamp(0.0, 1.0) * (STOPS.len() - 1) as f32;
+    let i = (t as usize).min(STOPS.len() - 2);
+    let f = t - i as f32;
+    let [r, g, b] = [0, 1, 2].map(|c| (STOPS[i][c] + (STOPS[i + 1][c] - STOPS[i][c]) * f) as u8);
+    Color32::from_rgb(r, g, b)
+}
+
+/// Calls `f` with the index and each point, on all cores for large batches.
+fn for_each_point(points: &mut [Point], f: impl Fn(usize, &mut Point) + Sync) {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if points.len() < PARALLEL_POINTS || threads == 1 {
+        points.iter_mut().enumerate().for_each(|(i, p)| f(i, p));
+        return;
+    }
+    let chunk_size = points.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        for (c, chunk) in points.chunks_mut(chunk_size).enumerate() {
+            let f = &f;
+            scope.spawn(move || {
+                for (i, point) in chunk.iter_mut().enumerate() {
+                    f(c * chunk_size + i, point);
+                }
+            });
+        }
+    });
+}
+
+/// How the points of a layer are joined on the map and in the exports.
+#[derive(PartialEq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum Geometry {
+    #[default]
+    Points,
+    Line,
+    Polygon,
+}
+
+impl Geometry {
+    pub const ALL: [Geometry; 3] = [Geometry::Points, Geometry::Line, Geometry::Polygon];
+
+    /// Joins the vertices, with an arrow halfway along each segment long
+    /// enough to show the direction.
+    pub fn paint(&self, painter: &Painter, vertices: &[Pos2], color: Color32) {
+        if *self == Geometry::Points || vertices.len() < 2 {
+            return;
+        }
+        let stroke = Stroke::new(2.0, color);
+        let mut segments: Vec<(Pos2, Pos2)> =
+            vertices.windows(2).map(|pair| (pair[0], pair[1])).collect();
+        if *self == Geometry::Polygon && vertices.len() >= 3 {
+            segments.push((vertices[vertices.len() - 1], vertices[0]));
+        }
+        // segments out of view are not drawn, the arrows stick out a little
+        let clip = painter.clip_rect().expand(10.0);
+        for (from, to) in segments {
+            if !Rect::from_two_pos(from, to).intersects(clip) {
+                continue;
+            }
+            painter.line_segment([from, to], stroke);
+            let length = from.distance(to);
+            if length < 40.0 {
+                continue;
+            }
+            let direction = (to - from) / length;
+            let normal = direction.rot90();
+            let tip = from.lerp(to, 0.5) + 4.0 * direction;
+            painter.add(Shape::convex_polygon(
+                vec![
+                    tip,
+                    tip - 9.0 * direction + 4.5 * normal,
+                    tip - 9.0 * direction - 4.5 * normal,
+                ],
+                color,
+                Stroke::NONE,
+            ));
+        }
+    }
+}
+
+impl fmt::Display for Geometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Geometry::Points => write!(f, "Points"),
+            Geometry::Line => write!(f, "Line"),
+            Geometry::Polygon => write!(f, "Polygon"),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Side {
+    Geo,
+    Utm,
+}
+
+/// Name, visibility and style of a layer, the settings edited from the layer
+/// list and the style window.
+#[derive(PartialEq, Clone)]
+pub struct Appearance {
+    pub name: String,
+    pub visible: bool,
+    pub color: Color32,
+    pub marker: MarkerStyle,
+    pub marker_size: f32,
+    pub color_by: ColorBy,
+    pub label: PointLabel,
+}
+
+/// A named dataset with its own input rows, conversion settings and map style.
+#[derive(Clone)]
+pub struct Layer {
+    /// Unique in the session and kept by copies, to find the layer again
+    /// after layers are removed or undone
+    id: u64,
+    pub name: String,
+    pub visible: bool,
+    pub color: Color32,
+    pub marker: MarkerStyle,
+    /// Marker radius, in points
+    pub marker_size: f32,
+    pub color_by: ColorBy,
+    pub label: PointLabel,
+    pub geometry: Geometry,
+    pub conversion_mode: ConversionMode,
+    pub raw_rows: Vec<[f64; 3]>,
+    pub detection: Option<Detection>,
+    pub interpretation: Interpretation,
+    pub axis_order: AxisOrder,
+    pub points: Vec<Point>,
+    pub utm_zone: u8,
+    pub hemisphere: Hemisphere,
+    /// Latitude band of UTM input, points outside it are flagged
+    pub band: Option<LatitudeBand>,
+}
+
+impl Layer {
+    pub fn new(name: String, color: Color32) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Layer {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name,
+            visible: true,
+            color,
+            marker: MarkerStyle::Circle,
+            marker_size: 5.0,
+            color_by: ColorBy::Layer,
+            label: PointLabel::None,
+            geometry: Geometry::Points,
+            conversion_mode: ConversionMode::LatLontoUTM,
+            raw_rows: vec![],
+            detection: None,
+            interpretation: Interpretation::LatLon,
+            axis_order: AxisOrder::Auto,
+            points: vec![],
+            utm_zone: 30,
+            hemisphere: Hemisphere::North,
+            band: None,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn appearance(&self) -> Appearance {
+        Appearance {
+            name: self.name.clone(),
+            visible: self.visible,
+            color: self.color,
+            marker: self.marker,
+            marker_size: self.marker_size,
+            color_by: self.color_by,
+            label: self.label,
+        }
+    }
+
+    pub fn set_appearance(&mut self, appearance: Appearance) {
+        self.name = appearance.name;
+        self.visible = appearance.visible;
+        self.color = appearance.color;
+        self.marker = appearance.marker;
+        self.marker_size = appearance.marker_size;
+        self.color_by = appearance.color_by;
+        self.label = appearance.label;
+    }
+
+    /// Lat/lon of UTM input, in the band of the layer if it has one.
+    fn utm_input_to_geo(&self, i: usize, utm: [f64; 2]) -> Result<[f64; 2], Error> {
+        match self.band {
+            Some(band) => utm_band_to_geo(i, utm, self.utm_zone, band),
+            None => utm_to_geo(i, utm, self.utm_zone, &self.hemisphere),
+        }
+    }
+
+    fn compute_geo_coords(&mut self) {
+        let mut points = std::mem::take(&mut self.points);
+        for_each_point(&mut points, |i, point| {
+            let Some(utm) = point.utm else { return };
+            match self.utm_input_to_geo(i, utm) {
+                Ok(geo) => {
+                    point.geo = Some(geo);
+                    point.error = None;
+                }
+                Err(e) => {
+                    point.geo = None;
+                    point.error = Some(e);
+                }
+            }
+        });
+        self.points = points;
+    }
+
+    fn compute_utm_coords(&mut self) {
+        for_each_point(&mut self.points, |i, point| {
+            let Some(geo) = point.geo else { return };
+            match geo_to_utm(i, geo, None) {
+                Ok(utm) => {
+                    point.utm = Some(utm);
+                    point.error = None;
+                }
+                Err(e) => {
+                    point.utm = None;
+                    point.error = Some(e);
+                }
+            }
+        });
+
+        let first_valid = self.valid_geo_coords().next();
+        if let Some(geo) = first_valid {
+            (self.utm_zone, self.hemisphere) = zone_of(geo);
+        }
+    }
+
+    pub fn convert(&mut self) -> Result<(), Error> {
+        if self.points.is_empty() {
+            return Err(Error::NoData);
+        }
+        // converting them as UTM would put every point in the wrong place
+        if self.interpretation == Interpretation::OtherProjected {
+            return Err(Error::NotUtm);
+        }
+        match self.conversion_mode {
+            ConversionMode::UTMtoLatLon => self.compute_geo_coords(),
+            ConversionMode::LatLontoUTM => self.compute_utm_coords(),
+        }
+        Ok(())
+    }
+
+    /// Whether converting `other` gives the points of this layer, so that a
+    /// conversion of `other` made meanwhile can replace them.
+    pub fn same_conversion_input(&self, other: &Layer) -> bool {
+        self.raw_rows == other.raw_rows
+            && self.interpretation == other.interpretation
+            && self.axis_order == other.axis_order
+            && self.conversion_mode == other.conversion_mode
+            && self.points.len() == other.points.len()
+            && (self.conversion_mode == ConversionMode::LatLontoUTM
+                || (self.utm_zone == other.utm_zone
+                    && self.hemisphere == other.hemisphere
+                    && self.band == other.band))
+    }
+
+    /// Rebuilds the points from the raw rows, unconverted.
+    pub fn set_interpretation(&mut self, interpretation: Interpretation) {
+        self.interpretation = interpretation;
+        self.conversion_mode = if interpretation.is_geographic() {
+            ConversionMode::LatLontoUTM
+        } else {
+            ConversionMode::UTMtoLatLon
+        };
+
+        self.points = self
+            .raw_rows
+            .iter()
+            .map(|&[a, b, h]| match interpretation {
+                Interpretation::LatLon => Point::from_geo(b, a, h),
+                Interpretation::LonLat => Point::from_geo(a, b, h),
+                Interpretation::NorthingEasting => Point::from_utm(b, a, h),
+                Interpretation::EastingNorthing | Interpretation::OtherProjected => {
+                    Point::from_utm(a, b, h)
+                }
+            })
+            .collect();
+    }
+
+    /// Switches UTM input to the southern hemisphere when its northings are
+    /// all negative: southern ones without the false northing. A few negative
+    /// ones are out of range in the north. Returns whether it switched.
+    pub fn south_for_negative_northings(&mut self) -> bool {
+        let mut northings = self.points.iter().filter_map(|p| p.utm).map(|[_x, y]| y);
+        let south = self.conversion_mode == ConversionMode::UTMtoLatLon
+            && self.hemisphere == Hemisphere::North
+            && self.interpretation != Interpretation::OtherProjected
+            && northings.next().is_some_and(|y| y < 0.0)
+            && northings.all(|y| y < 0.0);
+        if south {
+            self.hemisphere = Hemisphere::South;
+            self.band = None;
+        }
+        south
+    }
+
+    /// Writes the points back into the raw rows, so edits survive a later
+    /// change of interpretation.
+    pub fn store_raw_rows(&mut self) {
+        self.raw_rows = self
+            .points
+            .iter()
+            .map(|p| {
+                let [a, b] = match self.interpretation {
+                    Interpretation::LatLon => p.geo.map(|[lon, lat]| [lat, lon]),
+                    Interpretation::LonLat => p.geo,
+                    Interpretation::NorthingEasting => p.utm.map(|[x, y]| [y, x]),
+                    Interpretation::EastingNorthing | Interpretation::OtherProjected => p.utm,
+                }
+                .unwrap_or_default();
+                [a, b, p.height]
+            })
+            .collect();
+    }
+
+    /// Recomputes the counterpart of the side of point `i` that was edited.
+    pub fn reconvert_point(&mut self, i: usize, edited: Side) {
+        let point = &self.points[i];
+        let result = match (edited, point.geo, point.utm) {
+            (Side::Geo, Some(geo), _) => {
+                let zone = match self.conversion_mode {
+                    ConversionMode::UTMtoLatLon => Some(self.utm_zone),
+                    ConversionMode::LatLontoUTM => None,
+                };
+                geo_to_utm(i, geo, zone).map(|utm| [Some(geo), Some(utm)])
+            }
+            (Side::Utm, geo, Some(utm))
+                if self.interpretation != Interpretation::OtherProjected =>
+            {
+                // lat/lon points keep the zone they were in before the edit
+                match (&self.conversion_mode, geo) {
+                    (ConversionMode::LatLontoUTM, Some(geo)) => {
+                        let (zone, hemisphere) = zone_of(geo);
+                        utm_to_geo(i, utm, zone, &hemisphere)
+                    }
+                    _ => self.utm_input_to_geo(i, utm),
+                }
+                .map(|geo| [Some(geo), Some(utm)])
+            }
+            (_, geo, utm) => Ok([geo, utm]),
+        };
+        let point = &mut self.points[i];
+        match result {
+            Ok([geo, utm]) => {
+                (point.geo, point.utm) = (geo, utm);
+                point.error = None;
+            }
+            // the counterpart of the edit no longer matches it
+            Err(e) => {
+                match edited {
+                    Side::Geo => point.utm = None,
+                    Side::Utm => point.geo = None,
+                }
+                point.error = Some(e);
+            }
+        }
+    }
+
+    pub fn renumber_errors(&mut self) {
+        for (i, point) in self.points.iter_mut().enumerate() {
+            if let Some(Error::OutOfRange { point, .. } | Error::OutsideBand { point, .. }) =
+                &mut point.error
+            {
+                *point = i;
+            }
+        }
+    }
+
+    /// UTM zone and hemisphere of a converted point. Lat/lon input is
+    /// converted point by point, each in its own zone.
+    pub fn zone_of_point(&self, point: &Point) -> Option<(u8, Hemisphere)> {
+        match (&self.conversion_mode, point.geo) {
+            (ConversionMode::UTMtoLatLon, _) => Some((self.utm_zone, self.hemisphere.clone())),
+            (ConversionMode::LatLontoUTM, Some(geo)) if point.is_valid() => Some(zone_of(geo)),
+            (ConversionMode::LatLontoUTM, _) => None,
+        }
+    }
+
+    pub fn valid_geo_coords(&self) -> impl Iterator<Item = [f64; 2]> + '_ {
+        self.points
+            .iter()
+            .filter(|p| p.is_valid())
+            .filter_map(|p| p.geo)
+    }
+
+    /// Valid points, for the vertices of a line or polygon.
+    pub fn valid_points(&self) -> impl Iterator<Item = &Point> + '_ {
+        self.points.iter().filter(|p| p.is_valid())
+    }
+
+    /// Lowest and highest height of the valid points.
+    pub fn height_range(&self) -> Option<[f64; 2]> {
+        self.valid_points().fold(None, |range, p| match range {
+            None => Some([p.height, p.height]),
+            Some([min, max]) => Some([min.min(p.height), max.max(p.height)]),
+        })
+    }
+
+    /// Marker fill of a point; `height_range` is that of the layer.
+    pub fn marker_color(&self, point: &Point, height_range: Option<[f64; 2]>) -> Color32 {
+        match self.color_by {
+            ColorBy::Layer => self.color,
+            ColorBy::Height => match height_range {
+                Some([min, max]) if max > min => ramp(((point.height - min) / (max - min)) as f32),
+                _ => ramp(0.5),
+            },
+            ColorBy::Zone => match self.zone_of_point(point) {
+                Some((zone, _)) => LAYER_COLORS[zone as usize % LAYER_COLORS.len()],
+                None => self.color,
+            },
+        }
+    }
+
+    /// Editor for the marker and label style.
+    pub fn show_style(&mut self, ui: &mut egui::Ui, settings: &Settings) {
+        Grid::new("layer_style")
+            .num_columns(2)
+            .spacing([20.0, 7.0])
+            .show(ui, |ui| {
+                ui.label("Marker");
+                ComboBox::new("layer_marker", "")
+                    .selected_text(format!("{}", self.marker))
+                    .show_ui(ui, |ui| {
+                        for style in MarkerStyle::ALL {
+                            ui.selectable_value(&mut self.marker, style, format!("{}", style));
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Size");
+                ui.add(DragValue::new(&mut self.marker_size).range(2.0..=15.0));
+                ui.end_row();
+
+                ui.label("Colour");
+                ui.color_edit_button_srgba(&mut self.color);
+                ui.end_row();
+
+                ui.label("Colour by");
+                ComboBox::new("layer_color_by", "")
+                    .selected_text(format!("{}", self.color_by))
+                    .show_ui(ui, |ui| {
+                        for color_by in ColorBy::ALL {
+                            ui.selectable_value(
+                                &mut self.color_by,
+                                color_by,
+                                format!("{}", color_by),
+                            );
+                        }
+                    });
+                ui.end_row();
+
+                if self.color_by == ColorBy::Height
+                    && let Some([min, max]) = self.height_range()
+                {
+                    ui.label("");
+                    ui.horizontal(|ui| {
+                        ui.label(settings.format_height(min));
+                        for i in 0..=8 {
+                            ui.colored_label(ramp(i as f32 / 8.0), "■");
+                        }
+                        ui.label(settings.format_height(max));
+                    });
+                    ui.end_row();
+                }
+
+                ui.label("Labels");
+                ComboBox::new("layer_label", "")
+                    .selected_text(format!("{}", self.label))
+                    .show_ui(ui, |ui| {
+                        for label in PointLabel::ALL {
+                            ui.selectable_value(&mut self.label, label, format!("{}", label));
+                        }
+                    });
+                ui.end_row();
+            });
+    }
+
+    /// KML colour, aabbggrr.
+    pub fn kml_color(&self) -> String {
+        let [r, g, b, a] = self.color.to_srgba_unmultiplied();
+        format!("{:02x}{:02x}{:02x}{:02x}", a, b, g, r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::History;
+
+    fn layer(rows: Vec<[f64; 3]>, interpretation: Interpretation) -> Layer {
+        let mut layer = Layer::new("Test".to_string(), LAYER_COLORS[0]);
+        layer.raw_rows = rows;
+        layer.set_interpretation(interpretation);
+        layer
+    }
+
+    #[test]
+    fn test_compute_utm_coords() {
+        let mut layer = layer(
+            vec![
+                [-33.92487, 18.42406, 5.0],
+                [40.71435, -74.00597, 10.0],
+                [85.0, 0.0, 0.0],
+            ],
+            Interpretation::LatLon,
+        );
+        layer.convert().unwrap();
+        // the zone and hemisphere of the layer follow its first point
+        assert_eq!(
+            (layer.utm_zone, &layer.hemisphere),
+            (34, &Hemisphere::South)
+        );
+        let [x, y] = layer.points[0].utm.unwrap();
+        assert!((x - 261878.0).abs() < 0.5 && (y - 6243186.0).abs() < 0.5);
+        let [x, y] = layer.points[1].utm.unwrap();
+        assert!((x - 583960.0).abs() < 0.5 && (y - 4507523.0).abs() < 0.5);
+        assert_eq!(layer.points[1].height, 10.0);
+        assert!(matches!(
+            layer.points[2].error,
+            Some(Error::OutOfRange { point: 2, .. })
+        ));
+        assert_eq!(
+            layer.zone_of_point(&layer.points[1]),
+            Some((18, Hemisphere::North))
+        );
+    }
+
+    #[test]
+    fn test_compute_geo_coords() {
+        let mut layer = layer(
+            vec![[6243186.0, 261878.0, 0.0], [6243186.0, 50_000.0, 0.0]],
+            Interpretation::NorthingEasting,
+        );
+        layer.utm_zone = 34;
+        layer.hemisphere = Hemisphere::South;
+        layer.convert().unwrap();
+        let [lon, lat] = layer.points[0].geo.unwrap();
+        assert!((lon - 18.42406).abs() < 1e-5 && (lat + 33.92487).abs() < 1e-5);
+        assert!(layer.points[1].geo.is_none());
+        assert!(matches!(
+            layer.points[1].error,
+            Some(Error::OutOfRange {
+                point: 1,
+                coordinate: "Easting",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_convert_in_parallel() {
+        // enough points to be split across threads, each keeps its own index
+        let rows: Vec<[f64; 3]> = (0..3 * PARALLEL_POINTS)
+            .map(|i| {
+                [
+                    40.0 + i as f64 * 1e-4,
+                    if i % 1000 == 0 { 200.0 } else { -3.0 },
+                    0.0,
+                ]
+            })
+            .collect();
+        let mut layer = layer(rows, Interpretation::LatLon);
+        layer.convert().unwrap();
+        for (i, point) in layer.points.iter().enumerate() {
+            if i % 1000 == 0 {
+                assert!(matches!(point.error, Some(Error::OutOfRange { point, .. }) if point == i));
+            } else {
+                let expected = geo_to_utm(i, point.geo.unwrap(), None).unwrap();
+                assert_eq!(point.utm, Some(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn test_other_projected_is_not_converted() {
+        let mut layer = layer(
+            vec![[2_500_000.0, 1_200_000.0, 0.0]],
+            Interpretation::OtherProjected,
+        );
+        assert!(matches!(layer.convert(), Err(Error::NotUtm)));
+        assert!(layer.points[0].geo.is_none());
+        assert!(layer.points[0].is_valid());
+        layer.reconvert_point(0, Side::Utm);
+        assert!(layer.points[0].geo.is_none());
+    }
+
+    #[test]
+    fn test_reconvert_point() {
+        let mut layer = layer(
+            vec![
+                [41.65, -0.87, 0.0],
+                [41.66, -0.88, 0.0],
+                [41.67, -0.89, 0.0],
+            ],
+            Interpretation::LatLon,
+        );
+        layer.convert().unwrap();
+        let before = layer.points.clone();
+
+        // editing a lat/lon cell reconverts that point only
+        layer.points[1].geo = Some([-0.9, 41.7]);
+        layer.reconvert_point(1, Side::Geo);
+        assert_eq!(
+            layer.points[1].utm,
+            Some(geo_to_utm(1, [-0.9, 41.7], None).unwrap())
+        );
+        for i in [0, 2] {
+            assert_eq!(layer.points[i].geo, before[i].geo);
+            assert_eq!(layer.points[i].utm, before[i].utm);
+        }
+
+        // and the other way round, in the zone the point was in
+        let [x, y] = layer.points[1].utm.unwrap();
+        layer.points[1].utm = Some([x + 100.0, y]);
+        layer.reconvert_point(1, Side::Utm);
+        let [lon, lat] = layer.points[1].geo.unwrap();
+        assert!(lon > -0.9 && (lat - 41.7).abs() < 1e-4);
+
+        // an invalid edit marks the point with its own index
+        layer.points[2].geo = Some([-0.89, 95.0]);
+        layer.reconvert_point(2, Side::Geo);
+        assert!(matches!(
+            layer.points[2].error,
+            Some(Error::OutOfRange { point: 2, .. })
+        ));
+        assert_eq!(layer.points[2].utm, None);
+        assert!(layer.points[0].is_valid() && layer.points[1].is_valid());
+    }
+
+    #[test]
+    fn test_same_conversion_input() {
+        let rows = vec![[41.65, -0.87, 0.0], [41.66, 2.17, 0.0]];
+        let lat_lon = layer(rows.clone(), Interpretation::LatLon);
+        assert!(lat_lon.same_conversion_input(&lat_lon.clone()));
+        // the same rows read in the other axis order
+        let lon_lat = layer(rows, Interpretation::LonLat);
+        assert!(!lat_lon.same_conversion_input(&lon_lat));
+        let mut forced = lat_lon.clone();
+        forced.axis_order = AxisOrder::NorthFirst;
+        assert!(!lat_lon.same_conversion_input(&forced));
+        // the style does not matter
+        let mut styled = lat_lon.clone();
+        styled.color = LAYER_COLORS[1];
+        assert!(lat_lon.same_conversion_input(&styled));
+    }
+
+    #[test]
+    fn test_convert_empty() {
+        let mut layer = layer(vec![], Interpretation::LatLon);
+        assert!(matches!(layer.convert(), Err(Error::NoData)));
+    }
+
+    #[test]
+    fn test_south_for_negative_northings() {
+        // a few negative northings stay in the north, out of range
+        let mut mixed = layer(
+            vec![[500_000.0, -100.0, 0.0], [500_000.0, 100.0, 0.0]],
+            Interpretation::EastingNorthing,
+        );
+        assert!(!mixed.south_for_negative_northings());
+        assert_eq!(mixed.hemisphere, Hemisphere::North);
+
+        // read from the clipboard, undone and redone as one step
+        let mut history = History::default();
+        let mut current = Layer::new("Test".to_string(), LAYER_COLORS[0]);
+        let before = current.clone();
+        current.raw_rows = vec![
+            [500_000.0, -1_000_000.0, 0.0],
+            [600_000.0, -2_000_000.0, 0.0],
+        ];
+        current.set_interpretation(Interpretation::EastingNorthing);
+        assert!(current.south_for_negative_northings());
+        current.convert().unwrap();
+        history.record("Read from clipboard", before);
+        assert_eq!(current.band, None);
+        assert!(current.valid_geo_coords().all(|[_lon, lat]| lat < 0.0));
+        let converted: Vec<_> = current.valid_geo_coords().collect();
+
+        let (label, undone) = history.undo(current).unwrap();
+        assert_eq!(label, "Read from clipboard");
+        assert!(!history.can_undo());
+        assert_eq!(undone.hemisphere, Hemisphere::North);
+        assert!(undone.points.is_empty());
+
+        let (_label, redone) = history.redo(undone).unwrap();
+        assert_eq!(redone.hemisphere, Hemisphere::South);
+        assert_eq!(redone.valid_geo_coords().collect::<Vec<_>>(), converted);
+    }
+}
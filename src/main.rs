@@ -2,11 +2,29 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
 mod app;
+mod conversion;
+mod detection;
+mod error;
 mod expiration;
+mod export;
+mod history;
+mod labels;
+mod layer;
+mod measure;
+mod overlays;
+mod project;
+mod search;
+mod settings;
+mod status;
+mod tile_cache;
+mod tiles;
+mod validation;
 use app::CoordinatesSuite;
 use egui::IconData;
 use egui::ViewportBuilder;
 
+const APP_ID: &str = "Coordinates Suite";
+
 fn load_icon() -> IconData {
     let icon_bytes = include_bytes!("../assets/icon-256.png");
     let img = image::load_from_memory(icon_bytes)
@@ -28,7 +46,7 @@ fn main() -> eframe::Result<()> {
     let icon_data = load_icon();
     let native_options = eframe::NativeOptions {
         viewport: ViewportBuilder {
-            title: Some("Coordinates Suite".to_string()),
+            title: Some(APP_ID.to_string()),
             inner_size: Some([1150.0, 720.0].into()),
             min_inner_size: Some([1150.0, 720.0].into()),
             icon: Some(std::sync::Arc::new(icon_data)),
@@ -37,7 +55,7 @@ fn main() -> eframe::Result<()> {
         ..Default::default()
     };
     eframe::run_native(
-        "Coordinates Suite",
+        APP_ID,
         native_options,
         Box::new(|cc| Ok(Box::new(CoordinatesSuite::new(cc)))),
     )
@@ -0,0 +1,349 @@
+use crate::conversion::{A, F, project};
+use crate::settings::Settings;
+use egui::Grid;
+use std::f64::consts::{FRAC_PI_4, PI};
+use std::fmt;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum MeasureSource {
+    /// Vertices clicked on the map
+    Drawn,
+    Selection,
+    ActiveLayer,
+}
+
+impl fmt::Display for MeasureSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeasureSource::Drawn => write!(f, "Drawn on the map"),
+            MeasureSource::Selection => write!(f, "Selected points"),
+            MeasureSource::ActiveLayer => write!(f, "All points of the active layer"),
+        }
+    }
+}
+
+/// Geodesic distance in metres and the azimuths in degrees at both ends, from
+/// the first to the second [lon, lat] position, by Vincenty's inverse formula. `None`
+/// for nearly antipodal positions, where it does not converge.
+pub fn inverse([lon1, lat1]: [f64; 2], [lon2, lat2]: [f64; 2]) -> Option<(f64, f64, f64)> {
+    let b = A * (1.0 - F);
+    let l = (lon2 - lon1).to_radians();
+    let u1 = ((1.0 - F) * lat1.to_radians().tan()).atan();
+    let u2 = ((1.0 - F) * lat2.to_radians().tan()).atan();
+    let (sin_u1, cos_u1) = u1.sin_cos();
+    let (sin_u2, cos_u2) = u2.sin_cos();
+
+    let mut lambda = l;
+    for _ in 0..200 {
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let sin_sigma = ((cos_u2 * sin_lambda).powi(2)
+            + (cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda).powi(2))
+        .sqrt();
+        if sin_sigma == 0.0 {
+            return Some((0.0, 0.0, 0.0));
+        }
+        let cos_sigma = sin_u1 * sin_u2 + cos_u1 * cos_u2 * cos_lambda;
+        let sigma = sin_sigma.atan2(cos_sigma);
+        let sin_alpha = cos_u1 * cos_u2 * sin_lambda / sin_sigma;
+        let cos2_alpha = 1.0 - sin_alpha * sin_alpha;
+        // on the equator
+        let cos_2sigma_m = if cos2_alpha == 0.0 {
+            0.0
+        } else {
+            cos_sigma - 2.0 * sin_u1 * sin_u2 / cos2_alpha
+        };
+        let c = F / 16.0 * cos2_alpha * (4.0 + F * (4.0 - 3.0 * cos2_alpha));
+        let previous = lambda;
+        lambda = l
+            + (1.0 - c)
+                * F
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))));
+        if (lambda - previous).abs() > 1e-12 {
+            continue;
+        }
+
+        let u_sq = cos2_alpha * (A * A - b * b) / (b * b);
+        let big_a =
+            1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+        let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+        let delta_sigma = big_b
+            * sin_sigma
+            * (cos_2sigma_m
+                + big_b / 4.0
+                    * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m.powi(2))
+                        - big_b / 6.0
+                            * cos_2sigma_m
+                            * (-3.0 + 4.0 * sin_sigma.powi(2))
+                            * (-3.0 + 4.0 * cos_2sigma_m.powi(2))));
+        let distance = b * big_a * (sigma - delta_sigma);
+        let (sin_lambda, cos_lambda) = lambda.sin_cos();
+        let forward = (cos_u2 * sin_lambda).atan2(cos_u1 * sin_u2 - sin_u1 * cos_u2 * cos_lambda);
+        let back = (cos_u1 * sin_lambda).atan2(-sin_u1 * cos_u2 + cos_u1 * sin_u2 * cos_lambda);
+        return Some((
+            distance,
+            forward.to_degrees().rem_euclid(360.0),
+            back.to_degrees().rem_euclid(360.0),
+        ));
+    }
+    None
+}
+
+/// Area in square metres of the polygon with these [lon, lat] vertices, on
+/// the sphere of the same area as the WGS 84 ellipsoid (authalic latitudes).
+pub fn polygon_area(vertices: &[[f64; 2]]) -> f64 {
+    let e = (F * (2.0 - F)).sqrt();
+    let q = |lat: f64| {
+        let sin = lat.sin();
+        (1.0 - e * e)
+            * (sin / (1.0 - e * e * sin * sin)
+                - (1.0 / (2.0 * e)) * ((1.0 - e * sin) / (1.0 + e * sin)).ln())
+    };
+    let q_pole = q(PI / 2.0);
+    let radius_sq = A * A * q_pole / 2.0;
+    let authalic = |lat: f64| (q(lat.to_radians()) / q_pole).clamp(-1.0, 1.0).asin();
+
+    // spherical excess of the triangles each edge makes with the south pole,
+    // from half the polar distance of its ends
+    let excess: f64 = vertices
+        .iter()
+        .zip(vertices.iter().cycle().skip(1))
+        .map(|([lon1, lat1], [lon2, lat2])| {
+            // the short way round, also across the antimeridian
+            let d_lon = ((lon2 - lon1).to_radians() + PI).rem_euclid(2.0 * PI) - PI;
+            let (sin1, cos1) = (authalic(*lat1) / 2.0 + FRAC_PI_4).sin_cos();
+            let (sin2, cos2) = (authalic(*lat2) / 2.0 + FRAC_PI_4).sin_cos();
+            let k = sin1 * sin2;
+            2.0 * (k * d_lon.sin()).atan2(cos1 * cos2 + k * d_lon.cos())
+        })
+        .sum();
+    excess.abs() * radius_sq
+}
+
+pub struct Segment {
+    pub distance: f64,
+    pub azimuth: f64,
+    /// Distance on the UTM grid, `None` if an end is outside the zone range
+    pub grid_distance: Option<f64>,
+}
+
+/// Measurements along the vertices, in order.
+pub struct Measurement {
+    pub segments: Vec<Segment>,
+    pub length: f64,
+    pub grid_length: Option<f64>,
+    /// Closed polygon, with three vertices or more
+    pub perimeter: f64,
+    pub area: Option<f64>,
+    pub zone: u8,
+}
+
+impl Measurement {
+    pub fn new(vertices: &[[f64; 2]], zone: u8) -> Self {
+        let grid = |[lon, lat]: [f64; 2]| {
+            if !(-80.0..=84.0).contains(&lat) {
+                return None;
+            }
+            // northings continue across the equator, without the false northing
+            Some(project(zone, [lon, lat]))
+        };
+        let segments: Vec<Segment> = vertices
+            .windows(2)
+            .map(|pair| {
+                let (distance, azimuth, _) =
+                    inverse(pair[0], pair[1]).unwrap_or((f64::NAN, f64::NAN, f64::NAN));
+                let grid_distance = grid(pair[0])
+                    .zip(grid(pair[1]))
+                    .map(|([x1, y1], [x2, y2])| (x2 - x1).hypot(y2 - y1));
+                Segment {
+                    distance,
+                    azimuth,
+                    grid_distance,
+                }
+            })
+            .collect();
+        let length = segments.iter().map(|s| s.distance).sum();
+        let grid_length = segments.iter().map(|s| s.grid_distance).sum();
+        let (perimeter, area) = match (vertices.first(), vertices.last()) {
+            (Some(first), Some(last)) if vertices.len() >= 3 => {
+                let closing = inverse(*last, *first).map_or(f64::NAN, |(d, _, _)| d);
+                (length + closing, Some(polygon_area(vertices)))
+            }
+            _ => (length, None),
+        };
+        Measurement {
+            segments,
+            length,
+            grid_length,
+            perimeter,
+            area,
+            zone,
+        }
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui, settings: &Settings) {
+        let metres = |value: f64| format!("{} m", settings.format_number(value, 3));
+        if self.segments.is_empty() {
+            ui.label("Needs two points or more");
+            return;
+        }
+        egui::ScrollArea::vertical()
+            .max_height(240.0)
+            .show(ui, |ui| {
+                Grid::new("measure_segments")
+                    .striped(true)
+                    .num_columns(4)
+                    .show(ui, |ui| {
+                        ui.strong("Segment");
+                        ui.strong("Distance");
+                        ui.strong("Azimuth");
+                        ui.strong(format!("Grid distance (zone {})", self.zone));
+                        ui.end_row();
+                        for (i, segment) in self.segments.iter().enumerate() {
+                            ui.label(format!("{} → {}", i + 1, i + 2));
+                            ui.label(metres(segment.distance));
+                            ui.label(format!("{}°", settings.format_number(segment.azimuth, 4)));
+                            ui.label(segment.grid_distance.map_or("-".to_string(), metres));
+                            ui.end_row();
+                        }
+                    });
+            });
+        ui.separator();
+        Grid::new("measure_totals").num_columns(2).show(ui, |ui| {
+            ui.label("Length");
+            ui.label(metres(self.length));
+            ui.end_row();
+            if let Some(grid_length) = self.grid_length {
+                ui.label("Grid length");
+                ui.label(format!(
+                    "{}  (scale factor {:.6})",
+                    metres(grid_length),
+                    grid_length / self.length
+                ));
+                ui.end_row();
+            }
+            if let Some(area) = self.area {
+                ui.label("Perimeter");
+                ui.label(metres(self.perimeter));
+                ui.end_row();
+                ui.label("Area");
+                ui.label(format!(
+                    "{} m²  ({} ha)",
+                    settings.format_number(area, 1),
+                    settings.format_number(area / 10_000.0, 4)
+                ));
+                ui.end_row();
+            }
+        });
+    }
+}
+
+pub struct MeasureTool {
+    pub window_open: bool,
+    pub source: MeasureSource,
+    /// [lon, lat] of the vertices drawn on the map
+    pub vertices: Vec<[f64; 2]>,
+}
+
+impl Default for MeasureTool {
+    fn default() -> Self {
+        MeasureTool {
+            window_open: false,
+            source: MeasureSource::Drawn,
+            vertices: vec![],
+        }
+    }
+}
+
+impl MeasureTool {
+    /// Clicks on the map add vertices.
+    pub fn is_drawing(&self) -> bool {
+        self.window_open && self.source == MeasureSource::Drawn
+    }
+
+    pub fn show_source(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::new("measure_source", "Measure")
+            .selected_text(format!("{}", self.source))
+            .show_ui(ui, |ui| {
+                for source in [
+                    MeasureSource::Drawn,
+                    MeasureSource::Selection,
+                    MeasureSource::ActiveLayer,
+                ] {
+                    ui.selectable_value(&mut self.source, source, format!("{}", source));
+                }
+            });
+        if self.source == MeasureSource::Drawn {
+            ui.label("Click the map to add vertices, click a marker to snap to it.");
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(!self.vertices.is_empty(), egui::Button::new("Remove last"))
+                    .clicked()
+                {
+                    self.vertices.pop();
+                }
+                if ui
+                    .add_enabled(!self.vertices.is_empty(), egui::Button::new("Clear"))
+                    .clicked()
+                {
+                    self.vertices.clear();
+                }
+            });
+        }
+        ui.separator();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dms(degrees: f64, minutes: f64, seconds: f64) -> f64 {
+        degrees.signum() * (degrees.abs() + minutes / 60.0 + seconds / 3600.0)
+    }
+
+    #[test]
+    fn test_inverse() {
+        // Vincenty's example, Flinders Peak to Buninyong
+        let flinders_peak = [dms(144.0, 25.0, 29.5244), dms(-37.0, 57.0, 3.7203)];
+        let buninyong = [dms(143.0, 55.0, 35.3839), dms(-37.0, 39.0, 10.1561)];
+        let (distance, forward, back) = inverse(flinders_peak, buninyong).unwrap();
+        assert!((distance - 54_972.271).abs() < 1e-3);
+        assert!((forward - dms(306.0, 52.0, 5.37)).abs() < 0.01 / 3600.0);
+        // the azimuth at the second end, the reverse one plus 180°
+        assert!((back - dms(127.0, 10.0, 25.07) - 180.0).abs() < 0.01 / 3600.0);
+
+        assert_eq!(inverse(buninyong, buninyong), Some((0.0, 0.0, 0.0)));
+        // along the equator
+        let (distance, forward, back) = inverse([0.0, 0.0], [1.0, 0.0]).unwrap();
+        assert!((distance - A * 1f64.to_radians()).abs() < 1e-6);
+        assert_eq!((forward, back), (90.0, 90.0));
+    }
+
+    #[test]
+    fn test_inverse_antipodal() {
+        assert!(inverse([0.0, 0.0], [179.7, 0.0]).is_none());
+        assert!(inverse([0.0, 0.0], [179.9, 0.5]).is_none());
+        assert!(inverse([0.0, 0.0], [179.0, 0.5]).is_some());
+    }
+
+    #[test]
+    fn test_polygon_area() {
+        // an eighth of the ellipsoid, its area is 510 065 621.724 km²
+        let octant = polygon_area(&[[0.0, 0.0], [90.0, 0.0], [0.0, 90.0]]);
+        assert!((octant - 510_065_621_724_088.0 / 8.0).abs() < 1.0);
+        // the same in the other direction and across the antimeridian
+        let octant = polygon_area(&[[135.0, 0.0], [135.0, 90.0], [-135.0, 0.0]]);
+        assert!((octant - 510_065_621_724_088.0 / 8.0).abs() < 1.0);
+
+        // a degree square at the equator, 12 308.464 km² between the
+        // parallels, the geodesic on top bulges north a little
+        let square = polygon_area(&[[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]]);
+        assert!((square / 12_308_463_894.0 - 1.0).abs() < 1e-4);
+
+        assert_eq!(polygon_area(&[]), 0.0);
+        assert_eq!(polygon_area(&[[0.0, 0.0], [1.0, 1.0]]), 0.0);
+    }
+}
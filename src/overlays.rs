@@ -0,0 +1,426 @@
+use crate::conversion::{Hemisphere, project, unproject};
+use egui::{Align2, Color32, FontId, Painter, Pos2, Rect, Stroke, pos2, vec2};
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+use walkers::{Position, Projector, lon_lat};
+
+const ZONE_COLOR: Color32 = Color32::from_rgb(180, 30, 30);
+const GRID_COLOR: Color32 = Color32::from_rgb(120, 40, 160);
+const GRATICULE_COLOR: Color32 = Color32::from_rgb(40, 80, 140);
+/// Degrees, the first one giving few enough lines in view is used
+const GRATICULE_SPACINGS: [f64; 14] = [
+    30.0, 10.0, 5.0, 2.0, 1.0, 0.5, 0.25, 0.1, 0.05, 0.025, 0.01, 0.005, 0.0025, 0.001,
+];
+/// Kilometres
+const GRID_SPACINGS: [f64; 9] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0];
+const MAX_LINES: f64 = 12.0;
+/// Points per UTM grid line, they are curves on the map
+const GRID_LINE_STEPS: usize = 24;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Overlays {
+    pub zones: bool,
+    pub utm_grid: bool,
+    /// Kilometres, `None` picks it from the zoom
+    pub grid_spacing: Option<f64>,
+    pub graticule: bool,
+}
+
+/// A UTM grid zone: zone number, latitude band and its [west, south, east, north] bounds.
+struct ZoneCell {
+    zone: u8,
+    band: char,
+    bounds: [f64; 4],
+}
+
+/// All grid zones, with the Norway and Svalbard exceptions.
+static ZONE_CELLS: LazyLock<Vec<ZoneCell>> = LazyLock::new(|| {
+    let mut cells = vec![];
+    for (i, band) in "CDEFGHJKLMNPQRSTUVWX".chars().enumerate() {
+        let south = -80.0 + 8.0 * i as f64;
+        let north = if band == 'X' { 84.0 } else { south + 8.0 };
+        for zone in 1..=60u8 {
+            let mut west = -180.0 + 6.0 * (zone - 1) as f64;
+            let mut east = west + 6.0;
+            match (band, zone) {
+                ('V', 31) => east = 3.0,
+                ('V', 32) => west = 3.0,
+                ('X', 32 | 34 | 36) => continue,
+                ('X', 31) => east = 9.0,
+                ('X', 33) => (west, east) = (9.0, 21.0),
+                ('X', 35) => (west, east) = (21.0, 33.0),
+                ('X', 37) => west = 33.0,
+                _ => {}
+            }
+            cells.push(ZoneCell {
+                zone,
+                band,
+                bounds: [west, south, east, north],
+            });
+        }
+    }
+    cells
+});
+
+/// [west, south, east, north] bounds covering a zone in a hemisphere, south
+/// to north, one per run of latitude bands with the same width.
+fn zone_parts(zone: u8, hemisphere: &Hemisphere) -> Vec<[f64; 4]> {
+    let mut parts: Vec<[f64; 4]> = vec![];
+    let cells = ZONE_CELLS.iter().filter(|cell| {
+        cell.zone == zone && (cell.bounds[1] >= 0.0) == (*hemisphere == Hemisphere::North)
+    });
+    for cell in cells {
+        let [west, south, east, north] = cell.bounds;
+        match parts.last_mut() {
+            Some(last) if [last[0], last[2], last[3]] == [west, east, south] => last[3] = north,
+            _ => parts.push(cell.bounds),
+        }
+    }
+    parts
+}
+
+fn screen_rect(projector: &Projector, [west, south, east, north]: [f64; 4]) -> Rect {
+    Rect::from_two_pos(
+        projector.project(lon_lat(west, north)).to_pos2(),
+        projector.project(lon_lat(east, south)).to_pos2(),
+    )
+}
+
+fn label(painter: &Painter, pos: Pos2, anchor: Align2, text: String, color: Color32) {
+    let galley = painter.layout_no_wrap(text, FontId::proportional(11.0), color);
+    let rect = anchor.anchor_size(pos, galley.size() + vec2(4.0, 2.0));
+    painter.rect_filled(rect, 2.0, Color32::from_white_alpha(170));
+    painter.galley(rect.min + vec2(2.0, 1.0), galley, color);
+}
+
+/// Decimals needed to tell the lines of a spacing apart.
+fn decimals(spacing: f64) -> usize {
+    spacing
+        .to_string()
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.len())
+}
+
+impl Overlays {
+    pub fn show_menu(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.zones, "UTM zones");
+        ui.checkbox(&mut self.utm_grid, "UTM grid of the active zone");
+        ui.add_enabled_ui(self.utm_grid, |ui| {
+            egui::ComboBox::new("grid_spacing", "Grid spacing")
+                .selected_text(match self.grid_spacing {
+                    Some(km) => format!("{} km", km),
+                    None => "Auto".to_string(),
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.grid_spacing, None, "Auto");
+                    for km in GRID_SPACINGS {
+                        ui.selectable_value(&mut self.grid_spacing, Some(km), format!("{} km", km));
+                    }
+                });
+        });
+        ui.checkbox(&mut self.graticule, "Lat/Lon graticule");
+    }
+
+    /// Draws the enabled overlays; `zone` and `hemisphere` are those of the
+    /// active layer, for the UTM grid.
+    pub fn draw(
+        &self,
+        painter: &Painter,
+        projector: &Projector,
+        zone: u8,
+        hemisphere: &Hemisphere,
+    ) {
+        let rect = painter.clip_rect();
+        let north_west = projector.unproject(rect.left_top().to_vec2());
+        let south_east = projector.unproject(rect.right_bottom().to_vec2());
+        let view = [
+            north_west.x(),
+            south_east.y(),
+            south_east.x(),
+            north_west.y(),
+        ];
+        if self.graticule {
+            self.draw_graticule(painter, projector, view);
+        }
+        if self.utm_grid {
+            self.draw_utm_grid(painter, projector, view, zone, hemisphere);
+        }
+        if self.zones {
+            self.draw_zones(painter, projector);
+        }
+    }
+
+    fn draw_zones(&self, painter: &Painter, projector: &Projector) {
+        let view = painter.clip_rect();
+        let stroke = Stroke::new(1.5, ZONE_COLOR);
+        for cell in ZONE_CELLS.iter() {
+            let cell_rect = screen_rect(projector, cell.bounds);
+            if !cell_rect.intersects(view) {
+                continue;
+            }
+            painter.rect_stroke(cell_rect, 0.0, stroke, egui::StrokeKind::Middle);
+            let visible = cell_rect.intersect(view);
+            if visible.width() > 30.0 && visible.height() > 16.0 {
+                label(
+                    painter,
+                    visible.center(),
+                    Align2::CENTER_CENTER,
+                    format!("{}{}", cell.zone, cell.band),
+                    ZONE_COLOR,
+                );
+            }
+        }
+    }
+
+    fn draw_graticule(&self, painter: &Painter, projector: &Projector, view: [f64; 4]) {
+        let [west, south, east, north] = view;
+        let span = (east - west).max(north - south);
+        let spacing = GRATICULE_SPACINGS
+            .iter()
+            .rev()
+            .copied()
+            .find(|spacing| span / spacing <= MAX_LINES)
+            .unwrap_or(GRATICULE_SPACINGS[0]);
+        let decimals = decimals(spacing);
+        let rect = painter.clip_rect();
+        let stroke = Stroke::new(1.0, GRATICULE_COLOR.gamma_multiply(0.7));
+
+        for i in (west / spacing).ceil() as i64..=(east / spacing).floor() as i64 {
+            let lon = i as f64 * spacing;
+            let x = projector.project(lon_lat(lon, 0.0)).x;
+            painter.line_segment([pos2(x, rect.top()), pos2(x, rect.bottom())], stroke);
+            label(
+                painter,
+                pos2(x, rect.bottom() - 2.0),
+                Align2::CENTER_BOTTOM,
+                format!("{:.*}°", decimals, lon),
+                GRATICULE_COLOR,
+            );
+        }
+        let (south, north) = (south.max(-85.0), north.min(85.0));
+        for i in (south / spacing).ceil() as i64..=(north / spacing).floor() as i64 {
+            let lat = i as f64 * spacing;
+            let y = projector.project(lon_lat(0.0, lat)).y;
+            painter.line_segment([pos2(rect.left(), y), pos2(rect.right(), y)], stroke);
+            label(
+                painter,
+                pos2(rect.left() + 2.0, y),
+                Align2::LEFT_CENTER,
+                format!("{:.*}°", decimals, lat),
+                GRATICULE_COLOR,
+            );
+        }
+    }
+
+    fn draw_utm_grid(
+        &self,
+        painter: &Painter,
+        projector: &Projector,
+        [west, south, east, north]: [f64; 4],
+        zone: u8,
+        hemisphere: &Hemisphere,
+    ) {
+        // the grid is only drawn in its own zone and hemisphere, which are
+        // wider or narrower in some bands around Norway and Svalbard
+        for [zone_west, zone_south, zone_east, zone_north] in zone_parts(zone, hemisphere) {
+            let bounds = [
+                west.max(zone_west),
+                south.max(zone_south),
+                east.min(zone_east),
+                north.min(zone_north),
+            ];
+            if bounds[0] < bounds[2] && bounds[1] < bounds[3] {
+                self.draw_utm_grid_part(painter, projector, bounds, zone, hemisphere);
+            }
+        }
+    }
+
+    /// The UTM grid within `bounds`, part of the zone.
+    fn draw_utm_grid_part(
+        &self,
+        painter: &Painter,
+        projector: &Projector,
+        bounds: [f64; 4],
+        zone: u8,
+        hemisphere: &Hemisphere,
+    ) {
+        let [west, south, east, north] = bounds;
+        let painter = painter.with_clip_rect(screen_rect(projector, bounds));
+
+        // extent of the view in UTM, from its corners and edge midpoints. The
+        // northings have no false northing, which moves no grid line.
+        let mut eastings = vec![];
+        let mut northings = vec![];
+        for lon in [west, (west + east) / 2.0, east] {
+            for lat in [south, (south + north) / 2.0, north] {
+                let [x, y] = project(zone, [lon, lat]);
+                eastings.push(x);
+                northings.push(y);
+            }
+        }
+        let min_max = |values: &[f64]| {
+            values.iter().fold([f64::MAX, f64::MIN], |[min, max], &v| {
+                [min.min(v), max.max(v)]
+            })
+        };
+        let [min_e, max_e] = min_max(&eastings);
+        let [min_n, max_n] = min_max(&northings);
+        if min_e > max_e {
+            return;
+        }
+
+        let span_km = (max_e - min_e).max(max_n - min_n) / 1000.0;
+        let Some(spacing) = self.grid_spacing.or_else(|| {
+            GRID_SPACINGS
+                .iter()
+                .copied()
+                .find(|spacing| span_km / spacing <= MAX_LINES)
+        }) else {
+            return;
+        };
+        // a fixed spacing too fine for the zoom would draw thousands of lines
+        if span_km / spacing > 10.0 * MAX_LINES {
+            return;
+        }
+        let spacing = spacing * 1000.0;
+        let stroke = Stroke::new(1.0, GRID_COLOR.gamma_multiply(0.8));
+        let to_screen = |x: f64, y: f64| -> Pos2 {
+            let [lon, lat] = unproject(zone, [x, y]);
+            projector.project(Position::new(lon, lat)).to_pos2()
+        };
+        let clip = painter.clip_rect();
+
+        for i in (min_e / spacing).ceil() as i64..=(max_e / spacing).floor() as i64 {
+            let x = i as f64 * spacing;
+            let points: Vec<Pos2> = (0..=GRID_LINE_STEPS)
+                .map(|i| {
+                    to_screen(
+                        x,
+                        min_n + (max_n - min_n) * i as f64 / GRID_LINE_STEPS as f64,
+                    )
+                })
+                .collect();
+            let bottom = points
+                .iter()
+                .filter(|p| clip.contains(**p))
+                .max_by(|a, b| a.y.total_cmp(&b.y));
+            if let Some(bottom) = bottom {
+                label(
+                    &painter,
+                    pos2(bottom.x, clip.bottom() - 16.0),
+                    Align2::CENTER_BOTTOM,
+                    format!("{} E", x / 1000.0),
+                    GRID_COLOR,
+                );
+            }
+            painter.add(egui::Shape::line(points, stroke));
+        }
+        for i in (min_n / spacing).ceil() as i64..=(max_n / spacing).floor() as i64 {
+            let y = i as f64 * spacing;
+            let points: Vec<Pos2> = (0..=GRID_LINE_STEPS)
+                .map(|i| {
+                    to_screen(
+                        min_e + (max_e - min_e) * i as f64 / GRID_LINE_STEPS as f64,
+                        y,
+                    )
+                })
+                .collect();
+            let left = points
+                .iter()
+                .filter(|p| clip.contains(**p))
+                .min_by(|a, b| a.x.total_cmp(&b.x));
+            if let Some(left) = left {
+                label(
+                    &painter,
+                    pos2(clip.left() + 40.0, left.y),
+                    Align2::LEFT_CENTER,
+                    format!("{} N", (y + hemisphere.false_northing()) / 1000.0),
+                    GRID_COLOR,
+                );
+            }
+            painter.add(egui::Shape::line(points, stroke));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversion::zone_of;
+
+    #[test]
+    fn test_zone_cells() {
+        // 20 bands of 60 zones, without 32X, 34X and 36X
+        assert_eq!(ZONE_CELLS.len(), 20 * 60 - 3);
+        let bounds = |zone, band| {
+            ZONE_CELLS
+                .iter()
+                .find(|cell| cell.zone == zone && cell.band == band)
+                .map(|cell| cell.bounds)
+        };
+        assert_eq!(bounds(30, 'T'), Some([-6.0, 40.0, 0.0, 48.0]));
+        assert_eq!(bounds(31, 'V'), Some([0.0, 56.0, 3.0, 64.0]));
+        assert_eq!(bounds(32, 'V'), Some([3.0, 56.0, 12.0, 64.0]));
+        assert_eq!(bounds(33, 'X'), Some([9.0, 72.0, 21.0, 84.0]));
+        assert_eq!(bounds(32, 'X'), None);
+        assert_eq!(bounds(1, 'C'), Some([-180.0, -80.0, -174.0, -72.0]));
+
+        // each position is in the cell of its zone
+        for [lon, lat] in [
+            [-0.87, 41.65],
+            [2.5, 60.0],
+            [5.0, 60.0],
+            [15.0, 78.0],
+            [-70.0, -33.0],
+        ] {
+            let zone = zone_of([lon, lat]).0;
+            assert!(
+                ZONE_CELLS.iter().any(|cell| {
+                    let [west, south, east, north] = cell.bounds;
+                    cell.zone == zone
+                        && (west..east).contains(&lon)
+                        && (south..north).contains(&lat)
+                }),
+                "{} {}",
+                lon,
+                lat
+            );
+        }
+    }
+
+    #[test]
+    fn test_zone_parts() {
+        assert_eq!(zone_parts(30, &Hemisphere::North), [[-6.0, 0.0, 0.0, 84.0]]);
+        assert_eq!(
+            zone_parts(30, &Hemisphere::South),
+            [[-6.0, -80.0, 0.0, 0.0]]
+        );
+        assert_eq!(
+            zone_parts(32, &Hemisphere::North),
+            [
+                [6.0, 0.0, 12.0, 56.0],
+                [3.0, 56.0, 12.0, 64.0],
+                [6.0, 64.0, 12.0, 72.0]
+            ]
+        );
+        assert_eq!(
+            zone_parts(31, &Hemisphere::North),
+            [
+                [0.0, 0.0, 6.0, 56.0],
+                [0.0, 56.0, 3.0, 64.0],
+                [0.0, 64.0, 6.0, 72.0],
+                [0.0, 72.0, 9.0, 84.0]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decimals() {
+        assert_eq!(decimals(30.0), 0);
+        assert_eq!(decimals(1.0), 0);
+        assert_eq!(decimals(0.5), 1);
+        assert_eq!(decimals(0.25), 2);
+        assert_eq!(decimals(0.001), 3);
+    }
+}
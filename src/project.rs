@@ -0,0 +1,155 @@
+use crate::APP_ID;
+use crate::conversion::{ConversionMode, Hemisphere, LatitudeBand};
+use crate::detection::{AxisOrder, Detection, Interpretation};
+use crate::error::Error;
+use crate::layer::{ColorBy, Geometry, Layer, MarkerStyle, PointLabel};
+use crate::tiles::BaseMap;
+use egui::Color32;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const FORMAT_VERSION: u32 = 1;
+
+/// A layer as stored in a project file. The points are rebuilt from the rows,
+/// which hold any edits made to them.
+#[derive(Serialize, Deserialize)]
+pub struct LayerFile {
+    name: String,
+    visible: bool,
+    color: [u8; 4],
+    marker: MarkerStyle,
+    #[serde(default)]
+    marker_size: Option<f32>,
+    #[serde(default)]
+    color_by: ColorBy,
+    #[serde(default)]
+    label: PointLabel,
+    #[serde(default)]
+    geometry: Geometry,
+    conversion_mode: ConversionMode,
+    interpretation: Interpretation,
+    axis_order: AxisOrder,
+    detection: Option<Detection>,
+    utm_zone: u8,
+    hemisphere: Hemisphere,
+    #[serde(default)]
+    band: Option<LatitudeBand>,
+    rows: Vec<[f64; 3]>,
+}
+
+impl LayerFile {
+    fn from_layer(layer: &Layer) -> Self {
+        LayerFile {
+            name: layer.name.clone(),
+            visible: layer.visible,
+            color: layer.color.to_srgba_unmultiplied(),
+            marker: layer.marker,
+            marker_size: Some(layer.marker_size),
+            color_by: layer.color_by,
+            label: layer.label,
+            geometry: layer.geometry,
+            conversion_mode: layer.conversion_mode.clone(),
+            interpretation: layer.interpretation,
+            axis_order: layer.axis_order,
+            detection: layer.detection.clone(),
+            utm_zone: layer.utm_zone,
+            hemisphere: layer.hemisphere.clone(),
+            band: layer.band,
+            rows: layer.raw_rows.clone(),
+        }
+    }
+
+    fn into_layer(self) -> Layer {
+        let [r, g, b, a] = self.color;
+        let mut layer = Layer::new(self.name, Color32::from_rgba_unmultiplied(r, g, b, a));
+        layer.visible = self.visible;
+        layer.marker = self.marker;
+        if let Some(size) = self.marker_size {
+            layer.marker_size = size;
+        }
+        layer.color_by = self.color_by;
+        layer.label = self.label;
+        layer.geometry = self.geometry;
+        layer.axis_order = self.axis_order;
+        layer.detection = self.detection;
+        layer.raw_rows = self.rows;
+        layer.set_interpretation(self.interpretation);
+        layer.conversion_mode = self.conversion_mode;
+        layer.utm_zone = self.utm_zone;
+        layer.hemisphere = self.hemisphere;
+        layer.band = self.band;
+        layer
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProjectFile {
+    version: u32,
+    layers: Vec<LayerFile>,
+    active_layer: usize,
+    /// [lon, lat]
+    map_center: [f64; 2],
+    map_zoom: f64,
+    #[serde(default)]
+    base_map: Option<BaseMap>,
+}
+
+impl ProjectFile {
+    pub fn new(
+        layers: &[Layer],
+        active_layer: usize,
+        map_center: [f64; 2],
+        map_zoom: f64,
+        base_map: BaseMap,
+    ) -> Self {
+        ProjectFile {
+            version: FORMAT_VERSION,
+            layers: layers.iter().map(LayerFile::from_layer).collect(),
+            active_layer,
+            map_center,
+            map_zoom,
+            base_map: Some(base_map),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let project: ProjectFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if project.version > FORMAT_VERSION {
+            return Err(Error::Project(format!(
+                "format version {} is newer than this program",
+                project.version
+            )));
+        }
+        if project.layers.is_empty() {
+            return Err(Error::Project("no layers".to_string()));
+        }
+        Ok(project)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Layers, active layer index, map centre, zoom and base map (missing in
+    /// files saved before it was stored).
+    /// The layers are not converted yet.
+    pub fn into_parts(self) -> (Vec<Layer>, usize, [f64; 2], f64, Option<BaseMap>) {
+        let n_layers = self.layers.len();
+        let layers = self.layers.into_iter().map(LayerFile::into_layer).collect();
+        (
+            layers,
+            self.active_layer.min(n_layers - 1),
+            self.map_center,
+            self.map_zoom,
+            self.base_map,
+        )
+    }
+}
+
+/// Where the session is saved while the program runs, removed on a clean exit.
+/// Finding it at startup means the last session crashed.
+pub fn autosave_path() -> Option<PathBuf> {
+    eframe::storage_dir(APP_ID).map(|dir| dir.join("autosave.json"))
+}
@@ -0,0 +1,468 @@
+use crate::conversion::{Hemisphere, LatitudeBand, parse_number_rows, utm_band_to_geo, utm_to_geo};
+use crate::detection::{Interpretation, detect};
+use crate::error::Error;
+use crate::tile_cache::USER_AGENT;
+use egui::{Button, Key, ScrollArea, TextEdit};
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+use std::thread::JoinHandle;
+
+const MAX_RESULTS: usize = 20;
+/// A UTM zone and latitude band before the coordinates
+static ZONE_PREFIX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*(\d{1,2})\s*([C-HJ-NP-Xc-hj-np-x])\s+(.*)$").unwrap());
+
+/// A place or coordinate found by a search.
+#[derive(Debug, Clone)]
+pub struct Place {
+    pub name: String,
+    /// Country, or the coordinate system of a coordinate
+    pub detail: String,
+    /// [lon, lat]
+    pub position: [f64; 2],
+    /// Typed coordinate rather than a place name
+    pub is_coordinate: bool,
+}
+
+/// Resolves place names to positions.
+pub trait Geocoder: Send + Sync {
+    fn name(&self) -> &str;
+    fn search(&self, query: &str) -> Result<Vec<Place>, Error>;
+}
+
+/// Lowercase, without the accents of Latin letters, for matching names.
+fn fold(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'á' | 'à' | 'â' | 'ä' | 'ã' | 'å' => 'a',
+            'é' | 'è' | 'ê' | 'ë' => 'e',
+            'í' | 'ì' | 'î' | 'ï' => 'i',
+            'ó' | 'ò' | 'ô' | 'ö' | 'õ' => 'o',
+            'ú' | 'ù' | 'û' | 'ü' => 'u',
+            'ñ' => 'n',
+            'ç' => 'c',
+            c => c,
+        })
+        .collect()
+}
+
+struct Entry {
+    folded: String,
+    place: Place,
+    population: u64,
+}
+
+/// Offline place names: the bundled extract of world and Spanish cities, or
+/// a GeoNames dump such as `cities15000.txt`.
+pub struct Gazetteer {
+    entries: Vec<Entry>,
+}
+
+impl Gazetteer {
+    pub fn bundled() -> Self {
+        Self::parse(include_str!("../assets/gazetteer.tsv"))
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let gazetteer = Self::parse(&fs::read_to_string(path)?);
+        if gazetteer.entries.is_empty() {
+            return Err(Error::Search(format!(
+                "No places found in {}",
+                path.display()
+            )));
+        }
+        Ok(gazetteer)
+    }
+
+    /// Tab-separated name, country, latitude, longitude and population, or the
+    /// GeoNames columns.
+    fn parse(text: &str) -> Self {
+        let entries = text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let columns: Vec<&str> = line.split('\t').collect();
+                let [name, country, lat, lon, population] = match columns[..] {
+                    [name, country, lat, lon, population] => [name, country, lat, lon, population],
+                    // geonameid, name, asciiname, alternatenames, latitude, longitude,
+                    // feature class, feature code, country code, cc2, admin1-4, population
+                    [
+                        _,
+                        name,
+                        _,
+                        _,
+                        lat,
+                        lon,
+                        _,
+                        _,
+                        country,
+                        _,
+                        _,
+                        _,
+                        _,
+                        _,
+                        population,
+                        ..,
+                    ] => [name, country, lat, lon, population],
+                    _ => return None,
+                };
+                Some(Entry {
+                    folded: fold(name),
+                    place: Place {
+                        name: name.to_string(),
+                        detail: country.to_string(),
+                        position: [lon.parse().ok()?, lat.parse().ok()?],
+                        is_coordinate: false,
+                    },
+                    population: population.parse().unwrap_or(0),
+                })
+            })
+            .collect();
+        Gazetteer { entries }
+    }
+}
+
+impl Geocoder for Gazetteer {
+    fn name(&self) -> &str {
+        "Offline gazetteer"
+    }
+
+    /// Exact names first, then names starting with the query, then names
+    /// containing it; the most populated first within each.
+    fn search(&self, query: &str) -> Result<Vec<Place>, Error> {
+        let query = fold(query.trim());
+        if query.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut matches: Vec<(u8, &Entry)> = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let rank = if entry.folded == query {
+                    0
+                } else if entry.folded.starts_with(&query) {
+                    1
+                } else if entry.folded.contains(&query) {
+                    2
+                } else {
+                    return None;
+                };
+                Some((rank, entry))
+            })
+            .collect();
+        matches.sort_by(|(rank_a, a), (rank_b, b)| {
+            rank_a.cmp(rank_b).then(b.population.cmp(&a.population))
+        });
+        Ok(matches
+            .into_iter()
+            .take(MAX_RESULTS)
+            .map(|(_rank, entry)| entry.place.clone())
+            .collect())
+    }
+}
+
+/// An online geocoder with the Nominatim search API.
+pub struct Nominatim {
+    pub url: String,
+}
+
+impl Geocoder for Nominatim {
+    fn name(&self) -> &str {
+        "Nominatim"
+    }
+
+    fn search(&self, query: &str) -> Result<Vec<Place>, Error> {
+        let client = reqwest::blocking::Client::builder()
+            .user_agent(USER_AGENT)
+            .build()
+            .map_err(|e| Error::Search(e.to_string()))?;
+        let body = client
+            .get(format!("{}/search", self.url.trim_end_matches('/')))
+            .query(&[("q", query), ("format", "jsonv2"), ("limit", "10")])
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .map_err(|e| Error::Search(e.to_string()))?;
+        let response: serde_json::Value =
+            serde_json::from_str(&body).map_err(|e| Error::Search(e.to_string()))?;
+        let results = response
+            .as_array()
+            .ok_or_else(|| Error::Search("Unexpected response".to_string()))?;
+        Ok(results
+            .iter()
+            .filter_map(|result| {
+                let coordinate = |key: &str| result[key].as_str()?.parse::<f64>().ok();
+                Some(Place {
+                    name: result["display_name"].as_str()?.to_string(),
+                    detail: result["type"].as_str().unwrap_or_default().to_string(),
+                    position: [coordinate("lon")?, coordinate("lat")?],
+                    is_coordinate: false,
+                })
+            })
+            .collect())
+    }
+}
+
+/// Reads a coordinate in any of the supported input formats. UTM coordinates
+/// are in `zone` and `hemisphere` unless the query starts with its own zone
+/// and latitude band, e.g. `30T 676000 4610000`.
+pub fn parse_coordinate(
+    query: &str,
+    zone: u8,
+    hemisphere: &Hemisphere,
+    previous_region: Option<[f64; 2]>,
+) -> Option<Place> {
+    let (utm_zone, numbers) = match ZONE_PREFIX.captures(query) {
+        Some(captures) => {
+            let zone: u8 = captures[1].parse().ok()?;
+            let band = LatitudeBand::new(captures[2].chars().next()?)?;
+            (Some((zone, band)), captures.get(3)?.as_str())
+        }
+        None => (None, query),
+    };
+    let (rows, errors) = parse_number_rows(numbers);
+    let ([a, b, _height], true) = (*rows.first()?, rows.len() == 1 && errors.is_empty()) else {
+        return None;
+    };
+    let detection = detect(&rows, previous_region);
+    let interpretation = match utm_zone {
+        Some(_) if detection.best().is_geographic() => Interpretation::EastingNorthing,
+        _ => detection.best(),
+    };
+    let (position, detail) = match interpretation {
+        Interpretation::LatLon => ([b, a], "Lat/Lon".to_string()),
+        Interpretation::LonLat => ([a, b], "Lon/Lat".to_string()),
+        Interpretation::EastingNorthing | Interpretation::NorthingEasting => {
+            let utm = match interpretation {
+                Interpretation::EastingNorthing => [a, b],
+                _ => [b, a],
+            };
+            match utm_zone {
+                Some((zone, band)) => (
+                    utm_band_to_geo(0, utm, zone, band).ok()?,
+                    format!("UTM zone {}{}", zone, band),
+                ),
+                None => (
+                    utm_to_geo(0, utm, zone, hemisphere).ok()?,
+                    format!("UTM zone {} {}", zone, hemisphere),
+                ),
+            }
+        }
+        Interpretation::OtherProjected => return None,
+    };
+    let [lon, lat] = position;
+    if lat.abs() > 90.0 || lon.abs() > 180.0 {
+        return None;
+    }
+    Some(Place {
+        name: query.trim().to_string(),
+        detail,
+        position: [lon, lat],
+        is_coordinate: true,
+    })
+}
+
+pub enum SearchAction {
+    Go(Place),
+    Add(Place),
+}
+
+/// The search box over the map and its results.
+pub struct SearchBox {
+    query: String,
+    results: Vec<Place>,
+    gazetteer: Arc<dyn Geocoder>,
+    online: Option<JoinHandle<Result<Vec<Place>, Error>>>,
+    pub error: Option<Error>,
+}
+
+impl SearchBox {
+    pub fn new(gazetteer: Arc<dyn Geocoder>) -> Self {
+        SearchBox {
+            query: String::new(),
+            results: vec![],
+            gazetteer,
+            online: None,
+            error: None,
+        }
+    }
+
+    pub fn set_gazetteer(&mut self, gazetteer: Arc<dyn Geocoder>) {
+        self.gazetteer = gazetteer;
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.online.is_some()
+    }
+
+    /// Looks the query up with `geocoder` on a worker thread.
+    fn search_online(&mut self, geocoder: Arc<dyn Geocoder>) {
+        let query = self.query.clone();
+        self.online = Some(std::thread::spawn(move || geocoder.search(&query)));
+    }
+
+    fn poll_online(&mut self) {
+        let Some(handle) = self.online.take_if(|handle| handle.is_finished()) else {
+            return;
+        };
+        match handle.join() {
+            Ok(Ok(places)) => self.results = places,
+            Ok(Err(e)) => self.error = Some(e),
+            Err(_) => self.error = Some(Error::Search("The search stopped".to_string())),
+        }
+    }
+
+    /// `coordinate` reads the query as a coordinate, `online` is the online
+    /// geocoder, if any.
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        coordinate: impl Fn(&str) -> Option<Place>,
+        online: Option<Arc<dyn Geocoder>>,
+    ) -> Option<SearchAction> {
+        self.poll_online();
+        let mut action = None;
+        ui.horizontal(|ui| {
+            let response = ui.add(
+                TextEdit::singleline(&mut self.query)
+                    .hint_text("Search a place or coordinate")
+                    .desired_width(240.0),
+            );
+            if response.changed() {
+                self.results = coordinate(&self.query)
+                    .map(|place| vec![place])
+                    .or_else(|| self.gazetteer.search(&self.query).ok())
+                    .unwrap_or_default();
+            }
+            let enter = response.lost_focus() && ui.input(|input| input.key_pressed(Key::Enter));
+            if enter && let Some(first) = self.results.first() {
+                action = Some(SearchAction::Go(first.clone()));
+            }
+            if let Some(online) = online {
+                if self.is_searching() {
+                    ui.spinner();
+                } else if ui
+                    .add_enabled(!self.query.trim().is_empty(), Button::new("Online"))
+                    .on_hover_text(format!("Search with {}", online.name()))
+                    .clicked()
+                {
+                    self.search_online(online);
+                }
+            }
+            if !self.query.is_empty() && ui.small_button("✖").clicked() {
+                self.query.clear();
+                self.results.clear();
+            }
+        });
+        if self.query.is_empty() || self.results.is_empty() {
+            return action;
+        }
+        ScrollArea::vertical().max_height(220.0).show(ui, |ui| {
+            for place in &self.results {
+                ui.horizontal(|ui| {
+                    if ui
+                        .small_button("+")
+                        .on_hover_text("Add as a point of the active layer")
+                        .clicked()
+                    {
+                        action = Some(SearchAction::Add(place.clone()));
+                    }
+                    let text = format!("{}  ({})", place.name, place.detail);
+                    if ui.link(text).clicked() {
+                        action = Some(SearchAction::Go(place.clone()));
+                    }
+                });
+            }
+        });
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAZETTEER: &str = "# name\tcountry\tlatitude\tlongitude\tpopulation
+Zaragoza\tES\t41.65606\t-0.87734\t674317
+Cádiz\tES\t36.52978\t-6.29465\t116979
+Cadiz\tPH\t10.95870\t123.30860\t154723
+Cadiz Road\tUS\t40.0\t-90.0\t100
+Arcade\tUS\t34.07\t-83.56\t1000
+";
+
+    #[test]
+    fn test_parse_coordinate() {
+        let place = parse_coordinate("30T 676000 4610000", 31, &Hemisphere::South, None).unwrap();
+        let [lon, lat] = place.position;
+        assert!((lon + 0.888).abs() < 0.01 && (lat - 41.617).abs() < 0.01);
+        assert_eq!(place.detail, "UTM zone 30T");
+        assert!(place.is_coordinate);
+
+        let place = parse_coordinate("676000 4610000", 30, &Hemisphere::North, None).unwrap();
+        assert_eq!(place.detail, "UTM zone 30 North");
+
+        let place = parse_coordinate("41.65 -0.88", 30, &Hemisphere::North, None).unwrap();
+        assert_eq!(place.position, [-0.88, 41.65]);
+        assert_eq!(place.detail, "Lat/Lon");
+        // a longitude beyond ±90° can't be a latitude
+        let place = parse_coordinate("-120.5 41.65", 30, &Hemisphere::North, None).unwrap();
+        assert_eq!(place.position, [-120.5, 41.65]);
+        assert_eq!(place.detail, "Lon/Lat");
+    }
+
+    #[test]
+    fn test_parse_coordinate_rejected() {
+        for query in ["", "Zaragoza", "41.65", "1 2\n3 4", "30T", "99 99999999"] {
+            assert!(
+                parse_coordinate(query, 30, &Hemisphere::North, None).is_none(),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_gazetteer_layouts() {
+        let gazetteer = Gazetteer::parse(GAZETTEER);
+        assert_eq!(gazetteer.entries.len(), 5);
+        let zaragoza = &gazetteer.entries[0];
+        assert_eq!(zaragoza.place.name, "Zaragoza");
+        assert_eq!(zaragoza.place.detail, "ES");
+        assert_eq!(zaragoza.place.position, [-0.87734, 41.65606]);
+        assert_eq!(zaragoza.population, 674317);
+
+        let geonames = "3104324\tZaragoza\tZaragoza\tSaragossa,Saragosse\t41.65606\t-0.87734\tP\tPPLA2\tES\t\t52\tZ\t50297\t\t674317\t\t208\tEurope/Madrid\t2020-04-05
+not a place
+";
+        let gazetteer = Gazetteer::parse(geonames);
+        assert_eq!(gazetteer.entries.len(), 1);
+        let zaragoza = &gazetteer.entries[0];
+        assert_eq!(zaragoza.place.name, "Zaragoza");
+        assert_eq!(zaragoza.place.detail, "ES");
+        assert_eq!(zaragoza.place.position, [-0.87734, 41.65606]);
+        assert_eq!(zaragoza.population, 674317);
+    }
+
+    #[test]
+    fn test_search_ranking() {
+        let gazetteer = Gazetteer::parse(GAZETTEER);
+        let names = |query| -> Vec<String> {
+            gazetteer
+                .search(query)
+                .unwrap()
+                .into_iter()
+                .map(|place| place.name)
+                .collect()
+        };
+        // exact names first, the most populated first, with or without accents,
+        // then the names starting with the query, then those containing it
+        assert_eq!(names("cadiz"), ["Cadiz", "Cádiz", "Cadiz Road"]);
+        assert_eq!(names(" CÁDIZ "), ["Cadiz", "Cádiz", "Cadiz Road"]);
+        assert_eq!(names("cad"), ["Cadiz", "Cádiz", "Cadiz Road", "Arcade"]);
+        assert!(names("").is_empty());
+        assert!(names("Madrid").is_empty());
+    }
+}
@@ -0,0 +1,279 @@
+use crate::conversion::Hemisphere;
+use crate::layer::MarkerStyle;
+use crate::tiles::{BaseMap, CustomTiles};
+use egui::{ComboBox, DragValue, Grid};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum DecimalSeparator {
+    Point,
+    Comma,
+}
+
+impl fmt::Display for DecimalSeparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecimalSeparator::Point => write!(f, "Point (1.5)"),
+            DecimalSeparator::Comma => write!(f, "Comma (1,5)"),
+        }
+    }
+}
+
+/// User preferences, kept in the eframe storage between sessions.
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub default_zone: u8,
+    pub default_hemisphere: Hemisphere,
+    /// [lon, lat] the map shows when there are no points
+    pub home: [f64; 2],
+    pub geo_decimals: usize,
+    pub utm_decimals: usize,
+    pub decimal_separator: DecimalSeparator,
+    /// Base map of new projects
+    pub base_map: BaseMap,
+    /// Last custom tile service defined
+    pub custom_tiles: CustomTiles,
+    pub export_dir: Option<PathBuf>,
+    /// GeoNames file searched instead of the bundled place names
+    pub gazetteer: Option<PathBuf>,
+    /// Nominatim-style search service, empty for offline search only
+    pub geocoder_url: String,
+    pub marker_style: MarkerStyle,
+    pub marker_size: f32,
+    /// Group the markers of dense layers in clusters with their count
+    pub cluster_markers: bool,
+    pub panel_width: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            default_zone: 30,
+            default_hemisphere: Hemisphere::North,
+            home: [-0.869147, 41.651285],
+            geo_decimals: 6,
+            utm_decimals: 2,
+            decimal_separator: DecimalSeparator::Point,
+            base_map: BaseMap::OpenStreetMap,
+            custom_tiles: CustomTiles::default(),
+            export_dir: None,
+            gazetteer: None,
+            geocoder_url: "https://nominatim.openstreetmap.org".to_string(),
+            marker_style: MarkerStyle::Circle,
+            marker_size: 5.0,
+            cluster_markers: true,
+            panel_width: 420.0,
+        }
+    }
+}
+
+impl Settings {
+    pub fn format_number(&self, value: impl Into<f64>, decimals: usize) -> String {
+        let text = format!("{:.*}", decimals, value.into());
+        match self.decimal_separator {
+            DecimalSeparator::Point => text,
+            DecimalSeparator::Comma => text.replace('.', ","),
+        }
+    }
+
+    /// Tab separated pair, lat/lon order for a [lon, lat] position.
+    pub fn format_geo(&self, pair: Option<[f64; 2]>) -> String {
+        match pair {
+            Some([lon, lat]) => format!(
+                "{}\t{}",
+                self.format_number(lat, self.geo_decimals),
+                self.format_number(lon, self.geo_decimals)
+            ),
+            None => "\t".to_string(),
+        }
+    }
+
+    pub fn format_utm(&self, pair: Option<[f64; 2]>) -> String {
+        match pair {
+            Some([x, y]) => format!(
+                "{}\t{}",
+                self.format_number(x, self.utm_decimals),
+                self.format_number(y, self.utm_decimals)
+            ),
+            None => "\t".to_string(),
+        }
+    }
+
+    pub fn format_height(&self, height: f64) -> String {
+        self.format_number(height, self.utm_decimals)
+    }
+
+    /// `map_center` is offered as the home location.
+    pub fn show(&mut self, ui: &mut egui::Ui, map_center: Option<[f64; 2]>) {
+        Grid::new("settings")
+            .num_columns(2)
+            .spacing([20.0, 7.0])
+            .show(ui, |ui| {
+                ui.label("Default UTM zone");
+                ui.add(DragValue::new(&mut self.default_zone).range(1..=60));
+                ui.end_row();
+
+                ui.label("Default hemisphere");
+                ComboBox::new("default_hemisphere", "")
+                    .selected_text(format!("{}", self.default_hemisphere))
+                    .show_ui(ui, |ui| {
+                        for hemisphere in [Hemisphere::North, Hemisphere::South] {
+                            ui.selectable_value(
+                                &mut self.default_hemisphere,
+                                hemisphere.clone(),
+                                format!("{}", hemisphere),
+                            );
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Datum");
+                ui.add_enabled(false, egui::Label::new("WGS 84"))
+                    .on_disabled_hover_text(
+                        "The only datum supported, coordinates in other datums are converted \
+                         as if they were WGS 84, without a datum shift",
+                    );
+                ui.end_row();
+
+                ui.label("Home location");
+                ui.horizontal(|ui| {
+                    let [lon, lat] = &mut self.home;
+                    ui.add(
+                        DragValue::new(lat)
+                            .range(-90.0..=90.0)
+                            .speed(0.001)
+                            .prefix("Lat "),
+                    );
+                    ui.add(
+                        DragValue::new(lon)
+                            .range(-180.0..=180.0)
+                            .speed(0.001)
+                            .prefix("Lon "),
+                    );
+                    if let Some(center) = map_center
+                        && ui.button("Use map centre").clicked()
+                    {
+                        self.home = center;
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Lat/Lon decimals");
+                ui.add(DragValue::new(&mut self.geo_decimals).range(0..=10));
+                ui.end_row();
+
+                ui.label("UTM decimals");
+                ui.add(DragValue::new(&mut self.utm_decimals).range(0..=4));
+                ui.end_row();
+
+                ui.label("Decimal separator");
+                ComboBox::new("decimal_separator", "")
+                    .selected_text(format!("{}", self.decimal_separator))
+                    .show_ui(ui, |ui| {
+                        for separator in [DecimalSeparator::Point, DecimalSeparator::Comma] {
+                            ui.selectable_value(
+                                &mut self.decimal_separator,
+                                separator,
+                                format!("{}", separator),
+                            );
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Default base map");
+                ComboBox::new("base_map", "")
+                    .selected_text(format!("{}", self.base_map))
+                    .show_ui(ui, |ui| {
+                        for base_map in BaseMap::PRESETS {
+                            ui.selectable_value(
+                                &mut self.base_map,
+                                base_map.clone(),
+                                format!("{}", base_map),
+                            );
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Export folder");
+                ui.horizontal(|ui| {
+                    match &self.export_dir {
+                        Some(dir) => ui.label(dir.display().to_string()),
+                        None => ui.label("Last used"),
+                    };
+                    if ui.button("Choose...").clicked()
+                        && let Some(dir) = rfd::FileDialog::new().pick_folder()
+                    {
+                        self.export_dir = Some(dir);
+                    }
+                    if self.export_dir.is_some() && ui.button("Clear").clicked() {
+                        self.export_dir = None;
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Place names");
+                ui.horizontal(|ui| {
+                    match &self.gazetteer {
+                        Some(path) => ui.label(path.display().to_string()),
+                        None => ui.label("Bundled cities"),
+                    };
+                    if ui
+                        .button("Choose...")
+                        .on_hover_text("A GeoNames file, such as cities15000.txt")
+                        .clicked()
+                        && let Some(path) = rfd::FileDialog::new()
+                            .add_filter("GeoNames files", &["txt", "tsv"])
+                            .pick_file()
+                    {
+                        self.gazetteer = Some(path);
+                    }
+                    if self.gazetteer.is_some() && ui.button("Clear").clicked() {
+                        self.gazetteer = None;
+                    }
+                });
+                ui.end_row();
+
+                ui.label("Online search service");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.geocoder_url)
+                        .hint_text("Empty to search offline only")
+                        .desired_width(260.0),
+                );
+                ui.end_row();
+
+                ui.label("Marker style for new layers");
+                ComboBox::new("default_marker_style", "")
+                    .selected_text(format!("{}", self.marker_style))
+                    .show_ui(ui, |ui| {
+                        for style in MarkerStyle::ALL {
+                            ui.selectable_value(
+                                &mut self.marker_style,
+                                style,
+                                format!("{}", style),
+                            );
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("Marker size for new layers");
+                ui.add(DragValue::new(&mut self.marker_size).range(2.0..=15.0));
+                ui.end_row();
+
+                ui.label("Dense layers");
+                ui.checkbox(&mut self.cluster_markers, "Cluster markers");
+                ui.end_row();
+
+                ui.label("Side panel width");
+                ui.add(DragValue::new(&mut self.panel_width).range(380.0..=800.0));
+                ui.end_row();
+            });
+
+        ui.separator();
+        if ui.button("Reset to defaults").clicked() {
+            *self = Settings::default();
+        }
+    }
+}
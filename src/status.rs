@@ -0,0 +1,112 @@
+use chrono::{DateTime, Local};
+use egui::{Color32, RichText, ScrollArea};
+use std::fmt;
+
+/// Oldest notifications are dropped beyond this.
+const MAX_NOTIFICATIONS: usize = 500;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Severity {
+    Info,
+    Error,
+}
+
+pub struct Notification {
+    time: DateTime<Local>,
+    severity: Severity,
+    message: String,
+}
+
+impl Notification {
+    fn text(&self) -> RichText {
+        let text = RichText::new(format!(
+            "{}  {}",
+            self.time.format("%H:%M:%S"),
+            self.message
+        ));
+        match self.severity {
+            Severity::Info => text,
+            Severity::Error => text.color(Color32::LIGHT_RED),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct StatusLog {
+    notifications: Vec<Notification>,
+    window_open: bool,
+}
+
+impl StatusLog {
+    pub fn push(&mut self, severity: Severity, message: impl fmt::Display) {
+        self.notifications.push(Notification {
+            time: Local::now(),
+            severity,
+            message: message.to_string(),
+        });
+        if self.notifications.len() > MAX_NOTIFICATIONS {
+            self.notifications.remove(0);
+        }
+    }
+
+    pub fn info(&mut self, message: impl fmt::Display) {
+        self.push(Severity::Info, message);
+    }
+
+    pub fn error(&mut self, message: impl fmt::Display) {
+        self.push(Severity::Error, message);
+    }
+
+    pub fn show_bar(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let n_errors = self
+                .notifications
+                .iter()
+                .filter(|n| n.severity == Severity::Error)
+                .count();
+            if ui.button(format!("Log ({} errors)", n_errors)).clicked() {
+                self.window_open = !self.window_open;
+            }
+            if let Some(last) = self.notifications.last() {
+                ui.label(last.text());
+            }
+        });
+    }
+
+    pub fn show_window(&mut self, ctx: &egui::Context) {
+        let mut clear = false;
+        egui::Window::new("Notification log")
+            .open(&mut self.window_open)
+            .default_size([500.0, 300.0])
+            .show(ctx, |ui| {
+                if ui.button("Clear").clicked() {
+                    clear = true;
+                }
+                ui.separator();
+                ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
+                    for notification in &self.notifications {
+                        ui.label(notification.text());
+                    }
+                });
+            });
+        if clear {
+            self.notifications.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_notifications() {
+        let mut log = StatusLog::default();
+        for i in 0..MAX_NOTIFICATIONS + 10 {
+            log.error(i);
+        }
+        assert_eq!(log.notifications.len(), MAX_NOTIFICATIONS);
+        assert_eq!(log.notifications[0].message, "10");
+        assert_eq!(log.notifications[0].severity, Severity::Error);
+    }
+}
@@ -0,0 +1,584 @@
+use crate::APP_ID;
+use crate::error::Error;
+use egui::{Rect, pos2};
+use lru::LruCache;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use std::collections::HashSet;
+use std::f64::consts::PI;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError, sync_channel};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use walkers::sources::{Attribution, TileSource};
+use walkers::{Position, Texture, TextureWithUv, TileId, Tiles};
+
+const MEMORY_TILES: usize = 512;
+const WORKERS: usize = 4;
+/// Cached tiles older than this are downloaded again when there is network.
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 3600);
+/// A tile that could not be read is asked for again after this.
+const RETRY_AFTER: Duration = Duration::from_secs(30);
+pub const MAX_PREFETCH_TILES: usize = 5000;
+/// Size of all the disk caches above which the oldest tiles are removed, in
+/// bytes.
+const MAX_DISK_CACHE: u64 = 1 << 30;
+pub const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Where the tile images come from.
+pub enum TileOrigin {
+    /// Downloaded from a tile server and kept in `cache_dir`
+    Http {
+        source: Box<dyn TileSource + Send + Sync>,
+        cache_dir: Option<PathBuf>,
+        /// Whether the server allows bulk downloads, tile.openstreetmap.org
+        /// does not
+        prefetch: bool,
+    },
+    MbTiles(PathBuf),
+    /// `{z}/{x}/{y}.png` (or .jpg) files in a folder
+    Folder(PathBuf),
+}
+
+/// Per worker state, the SQLite connection can't be shared between threads.
+struct Reader {
+    origin: Arc<TileOrigin>,
+    client: Option<reqwest::blocking::Client>,
+    mbtiles: Option<Connection>,
+}
+
+impl Reader {
+    fn new(origin: Arc<TileOrigin>) -> Self {
+        let client = match *origin {
+            TileOrigin::Http { .. } => reqwest::blocking::Client::builder()
+                .user_agent(USER_AGENT)
+                .timeout(Duration::from_secs(15))
+                .build()
+                .ok(),
+            _ => None,
+        };
+        let mbtiles = match &*origin {
+            TileOrigin::MbTiles(path) => open_mbtiles(path).ok(),
+            _ => None,
+        };
+        Reader {
+            origin,
+            client,
+            mbtiles,
+        }
+    }
+
+    /// Image bytes of a tile, `None` if it is not available.
+    fn read(&self, tile_id: TileId) -> Option<Vec<u8>> {
+        match &*self.origin {
+            TileOrigin::Http {
+                source, cache_dir, ..
+            } => {
+                let cached = cache_dir.as_ref().map(|dir| cache_path(dir, tile_id));
+                if let Some(path) = &cached
+                    && is_fresh(path)
+                {
+                    return fs::read(path).ok();
+                }
+                match self.download(source.tile_url(tile_id)) {
+                    Some(bytes) => {
+                        if let Some(path) = &cached {
+                            store(path, &bytes);
+                        }
+                        Some(bytes)
+                    }
+                    // offline, an expired tile is better than none
+                    None => cached.and_then(|path| fs::read(path).ok()),
+                }
+            }
+            TileOrigin::MbTiles(_) => {
+                // MBTiles rows are counted from the south
+                let row = (1u32 << tile_id.zoom) - 1 - tile_id.y;
+                self.mbtiles
+                    .as_ref()?
+                    .query_row(
+                        "SELECT tile_data FROM tiles \
+                     WHERE zoom_level = ?1 AND tile_column = ?2 AND tile_row = ?3",
+                        (tile_id.zoom, tile_id.x, row),
+                        |row| row.get(0),
+                    )
+                    .ok()
+            }
+            TileOrigin::Folder(dir) => ["png", "jpg", "jpeg", "webp"].iter().find_map(|ext| {
+                fs::read(dir.join(format!(
+                    "{}/{}/{}.{}",
+                    tile_id.zoom, tile_id.x, tile_id.y, ext
+                )))
+                .ok()
+            }),
+        }
+    }
+
+    fn download(&self, url: String) -> Option<Vec<u8>> {
+        let response = self.client.as_ref()?.get(url).send().ok()?;
+        let bytes = response.error_for_status().ok()?.bytes().ok()?;
+        Some(bytes.to_vec())
+    }
+}
+
+fn cache_path(cache_dir: &Path, tile_id: TileId) -> PathBuf {
+    cache_dir.join(format!("{}/{}/{}", tile_id.zoom, tile_id.x, tile_id.y))
+}
+
+fn is_fresh(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .is_ok_and(|age| age < MAX_AGE)
+        })
+}
+
+fn store(path: &Path, bytes: &[u8]) {
+    if let Some(dir) = path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let _ = fs::write(path, bytes);
+}
+
+fn open_mbtiles(path: &Path) -> Result<Connection, Error> {
+    Ok(Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )?)
+}
+
+/// Attribution and maximum zoom from the metadata table of an MBTiles file.
+pub fn mbtiles_metadata(path: &Path) -> Result<(String, u8), Error> {
+    let connection = open_mbtiles(path)?;
+    let value = |name: &str| -> Result<Option<String>, Error> {
+        Ok(connection
+            .query_row(
+                "SELECT value FROM metadata WHERE name = ?1",
+                [name],
+                |row| row.get(0),
+            )
+            .optional()?)
+    };
+    let attribution = value("attribution")?.unwrap_or_default();
+    let max_zoom = match value("maxzoom")? {
+        Some(zoom) => zoom.parse().unwrap_or(19),
+        None => connection
+            .query_row("SELECT MAX(zoom_level) FROM tiles", [], |row| {
+                row.get::<_, Option<u8>>(0)
+            })?
+            .unwrap_or(19),
+    };
+    Ok((attribution, max_zoom))
+}
+
+/// Where the tiles of a server are cached, `key` tells the servers apart.
+pub fn cache_dir(key: &str) -> Option<PathBuf> {
+    eframe::storage_dir(APP_ID).map(|dir| dir.join("tiles").join(key))
+}
+
+/// FNV-1a, the same in every build, unlike the standard library hasher.
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Removes the oldest tiles of all the disk caches until they take at most
+/// `MAX_DISK_CACHE`.
+pub fn trim_disk_cache() {
+    if let Some(dir) = eframe::storage_dir(APP_ID) {
+        trim_dir(&dir.join("tiles"), MAX_DISK_CACHE);
+    }
+}
+
+/// Removes the least recently written files under `root` until they add up
+/// to `max_size` bytes or less.
+fn trim_dir(root: &Path, max_size: u64) {
+    let mut files = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else {
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                files.push((modified, metadata.len(), entry.path()));
+            }
+        }
+    }
+    let mut size: u64 = files.iter().map(|(_modified, len, _path)| len).sum();
+    files.sort_by_key(|(modified, _len, _path)| *modified);
+    for (_modified, len, path) in files {
+        if size <= max_size {
+            break;
+        }
+        if fs::remove_file(path).is_ok() {
+            size -= len;
+        }
+    }
+}
+
+/// Tiles covering the area between two corners, at every zoom of the range.
+pub fn tiles_in_extent(
+    north_west: Position,
+    south_east: Position,
+    zooms: std::ops::RangeInclusive<u8>,
+) -> Vec<TileId> {
+    let mut tile_ids = vec![];
+    for zoom in zooms {
+        let [min_x, min_y] = tile_xy(north_west, zoom);
+        let [max_x, max_y] = tile_xy(south_east, zoom);
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                tile_ids.push(TileId { x, y, zoom });
+            }
+            // stop early, the caller only needs to know it is too many
+            if tile_ids.len() > MAX_PREFETCH_TILES {
+                return tile_ids;
+            }
+        }
+    }
+    tile_ids
+}
+
+fn tile_xy(position: Position, zoom: u8) -> [u32; 2] {
+    let n = (1u32 << zoom) as f64;
+    let lat = position.y().clamp(-85.0511, 85.0511).to_radians();
+    let x = (position.x() + 180.0) / 360.0 * n;
+    let y = (1.0 - lat.tan().asinh() / PI) / 2.0 * n;
+    [
+        (x.max(0.0) as u32).min(n as u32 - 1),
+        (y.max(0.0) as u32).min(n as u32 - 1),
+    ]
+}
+
+/// Background download of tiles into the disk cache.
+pub struct Prefetch {
+    pub total: usize,
+    done: Arc<AtomicUsize>,
+    failed: Arc<AtomicUsize>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Prefetch {
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.done() == self.total || self.cancel.load(Ordering::Relaxed)
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Walkers only takes static attribution text, each distinct text is leaked
+/// once and reused when the base map is switched back.
+fn static_text(text: String) -> &'static str {
+    static TEXTS: LazyLock<Mutex<HashSet<&'static str>>> = LazyLock::new(Default::default);
+    let Ok(mut texts) = TEXTS.lock() else {
+        return "";
+    };
+    match texts.get(text.as_str()) {
+        Some(text) => text,
+        None => {
+            let text = &*text.leak();
+            texts.insert(text);
+            text
+        }
+    }
+}
+
+enum TileState {
+    Loading,
+    Loaded(Texture),
+    /// Not available when it was read, retried after [`RETRY_AFTER`]
+    Failed(Instant),
+}
+
+/// Tiles read from the disk cache or a local tileset, or downloaded in the
+/// background.
+pub struct CachedTiles {
+    origin: Arc<TileOrigin>,
+    attribution: &'static str,
+    max_zoom: u8,
+    textures: LruCache<TileId, TileState>,
+    request_tx: SyncSender<TileId>,
+    texture_rx: Receiver<(TileId, Option<Texture>)>,
+    ctx: egui::Context,
+}
+
+impl CachedTiles {
+    pub fn new(origin: TileOrigin, attribution: String, max_zoom: u8, ctx: &egui::Context) -> Self {
+        let origin = Arc::new(origin);
+        // a short queue, so that the tiles in view are loaded first
+        let (request_tx, request_rx) = sync_channel::<TileId>(WORKERS * 2);
+        let (texture_tx, texture_rx) = std::sync::mpsc::channel();
+        let request_rx = Arc::new(Mutex::new(request_rx));
+        for _ in 0..WORKERS {
+            let reader = Reader::new(origin.clone());
+            let request_rx = request_rx.clone();
+            let texture_tx = texture_tx.clone();
+            let ctx = ctx.clone();
+            // the workers end when the tiles are dropped and the queue closes
+            thread::spawn(move || {
+                loop {
+                    let next = match request_rx.lock() {
+                        Ok(rx) => rx.recv(),
+                        Err(_) => break,
+                    };
+                    let Ok(tile_id) = next else {
+                        break;
+                    };
+                    let texture = reader
+                        .read(tile_id)
+                        .and_then(|bytes| Texture::new(&bytes, &ctx).ok());
+                    if texture_tx.send((tile_id, texture)).is_err() {
+                        break;
+                    }
+                    ctx.request_repaint();
+                }
+            });
+        }
+        CachedTiles {
+            origin,
+            attribution: static_text(attribution),
+            max_zoom,
+            textures: LruCache::new(NonZeroUsize::new(MEMORY_TILES).unwrap_or(NonZeroUsize::MIN)),
+            request_tx,
+            texture_rx,
+            ctx: ctx.clone(),
+        }
+    }
+
+    pub fn can_prefetch(&self) -> bool {
+        matches!(
+            *self.origin,
+            TileOrigin::Http {
+                cache_dir: Some(_),
+                prefetch: true,
+                ..
+            }
+        )
+    }
+
+    pub fn max_zoom(&self) -> u8 {
+        self.max_zoom
+    }
+
+    /// Downloads the tiles into the disk cache, one at a time to go easy on
+    /// the server.
+    pub fn prefetch(&self, tile_ids: Vec<TileId>) -> Prefetch {
+        let prefetch = Prefetch {
+            total: tile_ids.len(),
+            done: Arc::new(AtomicUsize::new(0)),
+            failed: Arc::new(AtomicUsize::new(0)),
+            cancel: Arc::new(AtomicBool::new(false)),
+        };
+        let reader = Reader::new(self.origin.clone());
+        let (done, failed, cancel) = (
+            prefetch.done.clone(),
+            prefetch.failed.clone(),
+            prefetch.cancel.clone(),
+        );
+        thread::spawn(move || {
+            for tile_id in tile_ids {
+                if cancel.load(Ordering::Relaxed) {
+                    break;
+                }
+                if reader.read(tile_id).is_none() {
+                    failed.fetch_add(1, Ordering::Relaxed);
+                }
+                done.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        prefetch
+    }
+
+    fn receive_textures(&mut self) {
+        while let Ok((tile_id, texture)) = self.texture_rx.try_recv() {
+            let state = match texture {
+                Some(texture) => TileState::Loaded(texture),
+                None => {
+                    // repaint to retry even if the map is not moved
+                    self.ctx.request_repaint_after(RETRY_AFTER);
+                    TileState::Failed(Instant::now())
+                }
+            };
+            self.textures.put(tile_id, state);
+        }
+    }
+
+    fn request(&mut self, tile_id: TileId) {
+        match self.textures.peek(&tile_id) {
+            Some(TileState::Failed(at)) if at.elapsed() >= RETRY_AFTER => {}
+            Some(_) => return,
+            None => {}
+        }
+        match self.request_tx.try_send(tile_id) {
+            Ok(()) => {
+                self.textures.put(tile_id, TileState::Loading);
+            }
+            // asked again in the next frame
+            Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => {}
+        }
+    }
+}
+
+/// The tile at a lower zoom containing `tile_id`, and the part of it to show.
+fn lower_zoom_tile(tile_id: TileId, zoom: u8) -> (TileId, Rect) {
+    let scale = 1u32 << (tile_id.zoom - zoom);
+    let part = (scale as f32).recip();
+    let (dx, dy) = ((tile_id.x % scale) as f32, (tile_id.y % scale) as f32);
+    (
+        TileId {
+            x: tile_id.x / scale,
+            y: tile_id.y / scale,
+            zoom,
+        },
+        Rect::from_min_max(
+            pos2(dx * part, dy * part),
+            pos2((dx + 1.0) * part, (dy + 1.0) * part),
+        ),
+    )
+}
+
+impl Tiles for CachedTiles {
+    fn at(&mut self, tile_id: TileId) -> Option<TextureWithUv> {
+        self.receive_textures();
+        let zoom = tile_id.zoom.min(self.max_zoom);
+        self.request(lower_zoom_tile(tile_id, zoom).0);
+
+        // until it is loaded, show a part of a tile with less detail
+        (0..=zoom).rev().find_map(|zoom| {
+            let (lower_tile_id, uv) = lower_zoom_tile(tile_id, zoom);
+            match self.textures.get(&lower_tile_id) {
+                Some(TileState::Loaded(texture)) => Some(TextureWithUv {
+                    texture: texture.clone(),
+                    uv,
+                }),
+                _ => None,
+            }
+        })
+    }
+
+    fn attribution(&self) -> Attribution {
+        Attribution {
+            text: self.attribution,
+            url: "",
+            logo_light: None,
+            logo_dark: None,
+        }
+    }
+
+    fn tile_size(&self) -> u32 {
+        256
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}-{}", APP_ID, std::process::id(), name))
+    }
+
+    #[test]
+    fn test_cache_path() {
+        let tile_id = TileId {
+            x: 3,
+            y: 5,
+            zoom: 4,
+        };
+        assert_eq!(
+            cache_path(Path::new("cache"), tile_id),
+            Path::new("cache").join("4").join("3").join("5")
+        );
+    }
+
+    #[test]
+    fn test_mbtiles() {
+        let path = temp_path("test.mbtiles");
+        let _ = fs::remove_file(&path);
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE metadata (name TEXT, value TEXT);
+                 CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+                 INSERT INTO metadata VALUES ('attribution', 'Test'), ('maxzoom', '14');
+                 INSERT INTO tiles VALUES (2, 1, 3, x'01'), (2, 1, 0, x'02');",
+            )
+            .unwrap();
+        drop(connection);
+
+        assert_eq!(mbtiles_metadata(&path).unwrap(), ("Test".to_string(), 14));
+        let reader = Reader::new(Arc::new(TileOrigin::MbTiles(path.clone())));
+        // rows are counted from the south, y from the north
+        let read = |y| reader.read(TileId { x: 1, y, zoom: 2 });
+        assert_eq!(read(0), Some(vec![1]));
+        assert_eq!(read(3), Some(vec![2]));
+        assert_eq!(read(1), None);
+        drop(reader);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        assert_eq!(stable_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(stable_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(stable_hash(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn test_trim_dir() {
+        let root = temp_path("trim");
+        let _ = fs::remove_dir_all(&root);
+        let now = SystemTime::now();
+        for (i, name) in ["0/0/0", "1/0/0", "1/1/0", "1/1/1"].iter().enumerate() {
+            let path = root.join(name);
+            store(&path, &[0; 100]);
+            // the first one is the oldest
+            let modified = now - Duration::from_secs(100 * (4 - i as u64));
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+        trim_dir(&root, 250);
+        assert!(!root.join("0/0/0").exists() && !root.join("1/0/0").exists());
+        assert!(root.join("1/1/0").exists() && root.join("1/1/1").exists());
+        trim_dir(&root, 1000);
+        assert!(root.join("1/1/0").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_static_text() {
+        let text = static_text("Some attribution".to_string());
+        assert_eq!(text, "Some attribution");
+        assert!(std::ptr::eq(
+            text,
+            static_text("Some attribution".to_string())
+        ));
+    }
+}
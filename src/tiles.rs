@@ -0,0 +1,423 @@
+use crate::tile_cache::{CachedTiles, TileOrigin, cache_dir, mbtiles_metadata, stable_hash};
+use egui::{ComboBox, DragValue, Grid, TextEdit};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fmt;
+use std::hash::Hash;
+use std::path::PathBuf;
+use walkers::TileId;
+use walkers::sources::{Attribution, OpenStreetMap, TileSource};
+
+/// Half the width of the Web Mercator (EPSG:3857) world, in metres.
+const MERCATOR_EXTENT: f64 = PI * 6_378_137.0;
+
+/// Pixels of the whole world at zoom 0, the map uses 256 px tiles.
+const WORLD_PIXELS: f64 = 256.0;
+/// Latitude limit of Web Mercator.
+const MAX_LATITUDE: f64 = 85.051_128_78;
+
+/// Web Mercator y of a latitude, from 0 at the north edge to 1 at the south edge.
+fn mercator_y(lat: f64) -> f64 {
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0
+}
+
+fn mercator_lat(y: f64) -> f64 {
+    (PI * (1.0 - 2.0 * y)).sinh().atan().to_degrees()
+}
+
+/// Center [lon, lat] and zoom that fit the [lon, lat] positions in a map of
+/// `size` points, with `padding` points around them, at `max_zoom` at most.
+/// The extent takes the short way round, across the antimeridian if that is
+/// narrower.
+pub fn fit_view(
+    positions: &[[f64; 2]],
+    size: egui::Vec2,
+    padding: f32,
+    max_zoom: f64,
+) -> Option<([f64; 2], f64)> {
+    if positions.is_empty() {
+        return None;
+    }
+    // the extent in longitude is the circle minus the widest gap between positions
+    let mut lons: Vec<f64> = positions
+        .iter()
+        .map(|[lon, _lat]| (lon + 180.0).rem_euclid(360.0) - 180.0)
+        .collect();
+    lons.sort_by(f64::total_cmp);
+    let (mut west, mut gap) = (lons[0], 360.0 - (lons[lons.len() - 1] - lons[0]));
+    for pair in lons.windows(2) {
+        if pair[1] - pair[0] > gap {
+            (west, gap) = (pair[1], pair[1] - pair[0]);
+        }
+    }
+    let lon_span = 360.0 - gap;
+    let center_lon = (west + lon_span / 2.0 + 180.0).rem_euclid(360.0) - 180.0;
+
+    let (north, south) = positions
+        .iter()
+        .map(|[_lon, lat]| mercator_y(*lat))
+        .fold((f64::MAX, f64::MIN), |(north, south), y| {
+            (north.min(y), south.max(y))
+        });
+    let center_lat = mercator_lat((north + south) / 2.0);
+
+    let available = (size - egui::vec2(2.0 * padding, 2.0 * padding)).max(egui::vec2(1.0, 1.0));
+    let scale_x = available.x as f64 / (WORLD_PIXELS * lon_span / 360.0);
+    let scale_y = available.y as f64 / (WORLD_PIXELS * (south - north));
+    // a single position, or all on one spot, gets the closest zoom
+    let zoom = scale_x.min(scale_y).log2().clamp(0.0, max_zoom);
+    Some(([center_lon, center_lat], zoom))
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Hash, Serialize, Deserialize)]
+pub enum ServiceKind {
+    /// `{z}/{x}/{y}` template, rows counted from the north
+    Xyz,
+    /// `{z}/{x}/{y}` template, rows counted from the south
+    Tms,
+    Wms,
+    Wmts,
+}
+
+impl fmt::Display for ServiceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceKind::Xyz => write!(f, "XYZ"),
+            ServiceKind::Tms => write!(f, "TMS"),
+            ServiceKind::Wms => write!(f, "WMS"),
+            ServiceKind::Wmts => write!(f, "WMTS"),
+        }
+    }
+}
+
+/// A tile service given by its URL. WMS and WMTS services are requested in
+/// Web Mercator, the only projection the map can show.
+#[derive(PartialEq, Debug, Clone, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CustomTiles {
+    pub name: String,
+    pub kind: ServiceKind,
+    /// Template for XYZ and TMS, service endpoint for WMS and WMTS
+    pub url: String,
+    pub layer: String,
+    pub format: String,
+    pub tile_matrix_set: String,
+    pub attribution: String,
+    pub max_zoom: u8,
+}
+
+impl Default for CustomTiles {
+    fn default() -> Self {
+        CustomTiles {
+            name: "Custom".to_string(),
+            kind: ServiceKind::Xyz,
+            url: String::new(),
+            layer: String::new(),
+            format: "image/png".to_string(),
+            tile_matrix_set: "GoogleMapsCompatible".to_string(),
+            attribution: String::new(),
+            max_zoom: 19,
+        }
+    }
+}
+
+impl CustomTiles {
+    fn opentopomap() -> Self {
+        CustomTiles {
+            name: "OpenTopoMap".to_string(),
+            url: "https://tile.opentopomap.org/{z}/{x}/{y}.png".to_string(),
+            attribution: "OpenStreetMap contributors, SRTM | Style: OpenTopoMap (CC-BY-SA)"
+                .to_string(),
+            max_zoom: 17,
+            ..Default::default()
+        }
+    }
+
+    fn satellite() -> Self {
+        CustomTiles {
+            name: "Esri World Imagery".to_string(),
+            url: "https://server.arcgisonline.com/ArcGIS/rest/services/World_Imagery/MapServer/tile/{z}/{y}/{x}"
+                .to_string(),
+            attribution: "Esri, Maxar, Earthstar Geographics, and the GIS User Community"
+                .to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Spanish national orthophoto.
+    fn pnoa() -> Self {
+        CustomTiles {
+            name: "PNOA".to_string(),
+            kind: ServiceKind::Wmts,
+            url: "https://www.ign.es/wmts/pnoa-ma".to_string(),
+            layer: "OI.OrthoimageCoverage".to_string(),
+            format: "image/jpeg".to_string(),
+            attribution: "PNOA cedido por © Instituto Geográfico Nacional".to_string(),
+            max_zoom: 20,
+            ..Default::default()
+        }
+    }
+
+    /// WMS GetMap bounding box of a tile, in EPSG:3857.
+    fn bbox(tile_id: &TileId) -> [f64; 4] {
+        let size = 2.0 * MERCATOR_EXTENT / 2f64.powi(tile_id.zoom as i32);
+        let min_x = -MERCATOR_EXTENT + tile_id.x as f64 * size;
+        let max_y = MERCATOR_EXTENT - tile_id.y as f64 * size;
+        [min_x, max_y - size, min_x + size, max_y]
+    }
+
+    fn separator(&self) -> &'static str {
+        if self.url.contains('?') { "&" } else { "?" }
+    }
+
+    /// Editor for the service definition.
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        Grid::new("custom_tiles")
+            .num_columns(2)
+            .spacing([20.0, 7.0])
+            .show(ui, |ui| {
+                ui.label("Name");
+                ui.text_edit_singleline(&mut self.name);
+                ui.end_row();
+
+                ui.label("Service");
+                ComboBox::new("service_kind", "")
+                    .selected_text(format!("{}", self.kind))
+                    .show_ui(ui, |ui| {
+                        for kind in [
+                            ServiceKind::Xyz,
+                            ServiceKind::Tms,
+                            ServiceKind::Wms,
+                            ServiceKind::Wmts,
+                        ] {
+                            ui.selectable_value(&mut self.kind, kind, format!("{}", kind));
+                        }
+                    });
+                ui.end_row();
+
+                let url_hint = match self.kind {
+                    ServiceKind::Xyz | ServiceKind::Tms => "https://example.com/{z}/{x}/{y}.png",
+                    ServiceKind::Wms | ServiceKind::Wmts => "https://example.com/service",
+                };
+                ui.label("URL");
+                ui.add(
+                    TextEdit::singleline(&mut self.url)
+                        .hint_text(url_hint)
+                        .desired_width(320.0),
+                );
+                ui.end_row();
+
+                if matches!(self.kind, ServiceKind::Wms | ServiceKind::Wmts) {
+                    ui.label("Layer");
+                    ui.text_edit_singleline(&mut self.layer);
+                    ui.end_row();
+
+                    ui.label("Image format");
+                    ui.text_edit_singleline(&mut self.format);
+                    ui.end_row();
+                }
+                if self.kind == ServiceKind::Wmts {
+                    ui.label("Tile matrix set");
+                    ui.text_edit_singleline(&mut self.tile_matrix_set);
+                    ui.end_row();
+                }
+
+                ui.label("Attribution");
+                ui.add(TextEdit::singleline(&mut self.attribution).desired_width(320.0));
+                ui.end_row();
+
+                ui.label("Max zoom");
+                ui.add(DragValue::new(&mut self.max_zoom).range(1..=22));
+                ui.end_row();
+            });
+    }
+}
+
+/// `CustomTiles` as a walkers source, its attribution is shown by `CachedTiles`.
+struct CustomSource(CustomTiles);
+
+impl TileSource for CustomSource {
+    fn tile_url(&self, tile_id: TileId) -> String {
+        let tiles = &self.0;
+        match tiles.kind {
+            ServiceKind::Xyz | ServiceKind::Tms => {
+                let y = match tiles.kind {
+                    ServiceKind::Tms => (1 << tile_id.zoom) - 1 - tile_id.y,
+                    _ => tile_id.y,
+                };
+                tiles
+                    .url
+                    .replace("{z}", &tile_id.zoom.to_string())
+                    .replace("{x}", &tile_id.x.to_string())
+                    .replace("{y}", &y.to_string())
+            }
+            ServiceKind::Wms => {
+                let [min_x, min_y, max_x, max_y] = CustomTiles::bbox(&tile_id);
+                format!(
+                    "{}{}SERVICE=WMS&REQUEST=GetMap&VERSION=1.3.0&LAYERS={}&STYLES=\
+                     &CRS=EPSG:3857&BBOX={},{},{},{}&WIDTH=256&HEIGHT=256&FORMAT={}",
+                    tiles.url,
+                    tiles.separator(),
+                    tiles.layer,
+                    min_x,
+                    min_y,
+                    max_x,
+                    max_y,
+                    tiles.format
+                )
+            }
+            ServiceKind::Wmts => format!(
+                "{}{}SERVICE=WMTS&REQUEST=GetTile&VERSION=1.0.0&LAYER={}&STYLE=default\
+                 &TILEMATRIXSET={}&TILEMATRIX={}&TILEROW={}&TILECOL={}&FORMAT={}",
+                tiles.url,
+                tiles.separator(),
+                tiles.layer,
+                tiles.tile_matrix_set,
+                tile_id.zoom,
+                tile_id.y,
+                tile_id.x,
+                tiles.format
+            ),
+        }
+    }
+
+    fn attribution(&self) -> Attribution {
+        Attribution {
+            text: "",
+            url: "",
+            logo_light: None,
+            logo_dark: None,
+        }
+    }
+
+    fn max_zoom(&self) -> u8 {
+        self.0.max_zoom
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
+pub enum BaseMap {
+    OpenStreetMap,
+    OpenTopoMap,
+    Satellite,
+    Pnoa,
+    Custom(CustomTiles),
+    MbTiles(PathBuf),
+    /// `{z}/{x}/{y}` tile pyramid in a folder
+    Folder(PathBuf),
+}
+
+impl BaseMap {
+    pub const PRESETS: [BaseMap; 4] = [
+        BaseMap::OpenStreetMap,
+        BaseMap::OpenTopoMap,
+        BaseMap::Satellite,
+        BaseMap::Pnoa,
+    ];
+
+    pub fn tiles(&self, ctx: &egui::Context) -> CachedTiles {
+        let tiles = match self {
+            BaseMap::OpenStreetMap => {
+                let origin = TileOrigin::Http {
+                    source: Box::new(OpenStreetMap),
+                    cache_dir: cache_dir("openstreetmap"),
+                    // https://operations.osmfoundation.org/policies/tiles/
+                    prefetch: false,
+                };
+                let attribution = "OpenStreetMap contributors".to_string();
+                return CachedTiles::new(origin, attribution, 19, ctx);
+            }
+            BaseMap::OpenTopoMap => CustomTiles::opentopomap(),
+            BaseMap::Satellite => CustomTiles::satellite(),
+            BaseMap::Pnoa => CustomTiles::pnoa(),
+            BaseMap::Custom(tiles) => tiles.clone(),
+            BaseMap::MbTiles(path) => {
+                // an unreadable file shows an empty map, it is checked when chosen
+                let (attribution, max_zoom) = mbtiles_metadata(path).unwrap_or((String::new(), 19));
+                return CachedTiles::new(
+                    TileOrigin::MbTiles(path.clone()),
+                    attribution,
+                    max_zoom,
+                    ctx,
+                );
+            }
+            BaseMap::Folder(path) => {
+                return CachedTiles::new(TileOrigin::Folder(path.clone()), String::new(), 19, ctx);
+            }
+        };
+        // what the tiles look like, not the name or attribution
+        let key = format!(
+            "{:?}\n{}\n{}\n{}\n{}",
+            tiles.kind, tiles.url, tiles.layer, tiles.format, tiles.tile_matrix_set
+        );
+        let origin = TileOrigin::Http {
+            cache_dir: cache_dir(&format!("{:016x}", stable_hash(key.as_bytes()))),
+            source: Box::new(CustomSource(tiles.clone())),
+            prefetch: true,
+        };
+        CachedTiles::new(origin, tiles.attribution, tiles.max_zoom, ctx)
+    }
+}
+
+impl fmt::Display for BaseMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BaseMap::OpenStreetMap => write!(f, "OpenStreetMap"),
+            BaseMap::OpenTopoMap => write!(f, "OpenTopoMap"),
+            BaseMap::Satellite => write!(f, "Satellite (Esri)"),
+            BaseMap::Pnoa => write!(f, "PNOA orthophoto (Spain)"),
+            BaseMap::Custom(tiles) => write!(f, "{}", tiles.name),
+            BaseMap::MbTiles(path) | BaseMap::Folder(path) => match path.file_name() {
+                Some(name) => write!(f, "{}", name.to_string_lossy()),
+                None => write!(f, "{}", path.display()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: egui::Vec2 = egui::vec2(800.0, 600.0);
+
+    #[test]
+    fn test_fit_view_single_point() {
+        assert!(fit_view(&[], SIZE, 40.0, 17.0).is_none());
+        let ([lon, lat], zoom) = fit_view(&[[-0.87, 41.65]], SIZE, 40.0, 17.0).unwrap();
+        assert!((lon + 0.87).abs() < 1e-9 && (lat - 41.65).abs() < 1e-9);
+        assert_eq!(zoom, 17.0);
+    }
+
+    #[test]
+    fn test_fit_view_zoom() {
+        // 90° of longitude on the equator in 720 px: a quarter of the world in
+        // 2^zoom · 256 px
+        let ([lon, lat], zoom) = fit_view(&[[0.0, -1.0], [90.0, 1.0]], SIZE, 40.0, 17.0).unwrap();
+        assert!((lon - 45.0).abs() < 1e-9 && lat.abs() < 1e-9);
+        assert!((zoom - (720.0f64 / 64.0).log2()).abs() < 1e-9);
+        // a world larger than the map is not zoomed out beyond zoom 0
+        let world = [[-179.0, -80.0], [0.0, 80.0], [179.0, 0.0]];
+        let (_, zoom) = fit_view(&world, egui::vec2(200.0, 200.0), 40.0, 17.0).unwrap();
+        assert_eq!(zoom, 0.0);
+    }
+
+    #[test]
+    fn test_fit_view_antimeridian() {
+        let ([lon, _lat], zoom) =
+            fit_view(&[[179.0, -17.0], [-179.0, -18.0]], SIZE, 40.0, 17.0).unwrap();
+        assert!((lon.abs() - 180.0).abs() < 1e-9);
+        // 2° wide, not 358°
+        assert!(zoom > 8.0);
+    }
+
+    #[test]
+    fn test_mercator() {
+        assert!((mercator_y(0.0) - 0.5).abs() < 1e-12);
+        assert!(mercator_y(MAX_LATITUDE).abs() < 1e-9);
+        for lat in [-80.0, -33.9, 0.0, 41.65, 84.0] {
+            assert!((mercator_lat(mercator_y(lat)) - lat).abs() < 1e-9);
+        }
+    }
+}
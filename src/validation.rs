@@ -0,0 +1,433 @@
+use crate::conversion::{ConversionMode, geo_to_utm, parse_numbers, utm_to_geo, zone_of};
+use crate::layer::Layer;
+use crate::measure::inverse;
+use crate::settings::Settings;
+use egui::{Color32, DragValue, Grid, RichText, ScrollArea};
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// Round-trip check of one point: its input converted and converted back.
+pub struct Check {
+    pub point: usize,
+    /// [lon, lat] or [easting, northing], in the input system
+    pub input: [f64; 2],
+    pub converted: [f64; 2],
+    pub back: [f64; 2],
+    /// Distance between the input and the point converted back, in metres
+    pub residual: f64,
+    /// Distance between the converted point and the reference, in metres
+    pub reference_residual: Option<f64>,
+}
+
+/// Round trip of the points of a layer, from the input system to the other
+/// and back, compared against the tolerance.
+pub struct Report {
+    pub layer: String,
+    pub mode: ConversionMode,
+    pub tolerance: f64,
+    pub checks: Vec<Check>,
+    /// Points without a conversion to check, with the reason
+    pub skipped: Vec<(usize, String)>,
+}
+
+impl Report {
+    /// `reference` holds the expected converted coordinates, row by row: easting
+    /// and northing, or latitude and longitude. Rows without them are `None`.
+    pub fn new(layer: &Layer, tolerance: f64, reference: &[Option<[f64; 2]>]) -> Self {
+        let mut checks = vec![];
+        let mut skipped = vec![];
+        for (i, point) in layer.points.iter().enumerate() {
+            if let Some(error) = &point.error {
+                skipped.push((i, error.to_string()));
+                continue;
+            }
+            let check = match (&layer.conversion_mode, point.geo, point.utm) {
+                (ConversionMode::LatLontoUTM, Some(geo), Some(utm)) => {
+                    let (zone, hemisphere) = zone_of(geo);
+                    utm_to_geo(i, utm, zone, &hemisphere).map(|back| Check {
+                        point: i,
+                        input: geo,
+                        converted: utm,
+                        back,
+                        residual: geodesic(geo, back),
+                        reference_residual: reference
+                            .get(i)
+                            .copied()
+                            .flatten()
+                            .map(|[x, y]| (x - utm[0]).hypot(y - utm[1])),
+                    })
+                }
+                (ConversionMode::UTMtoLatLon, Some(geo), Some(utm)) => {
+                    geo_to_utm(i, geo, Some(layer.utm_zone)).map(|back| Check {
+                        point: i,
+                        input: utm,
+                        converted: geo,
+                        back,
+                        residual: (back[0] - utm[0]).hypot(back[1] - utm[1]),
+                        reference_residual: reference
+                            .get(i)
+                            .copied()
+                            .flatten()
+                            .map(|[lat, lon]| geodesic(geo, [lon, lat])),
+                    })
+                }
+                _ => {
+                    skipped.push((i, "Not converted".to_string()));
+                    continue;
+                }
+            };
+            match check {
+                Ok(check) => checks.push(check),
+                Err(e) => skipped.push((i, e.to_string())),
+            }
+        }
+        Report {
+            layer: layer.name.clone(),
+            mode: layer.conversion_mode.clone(),
+            tolerance,
+            checks,
+            skipped,
+        }
+    }
+
+    pub fn is_flagged(&self, check: &Check) -> bool {
+        check.residual > self.tolerance
+            || check
+                .reference_residual
+                .is_some_and(|residual| residual > self.tolerance)
+    }
+
+    pub fn flagged(&self) -> impl Iterator<Item = &Check> + '_ {
+        self.checks.iter().filter(|check| self.is_flagged(check))
+    }
+
+    pub fn max_residual(&self) -> f64 {
+        self.checks.iter().map(|c| c.residual).fold(0.0, f64::max)
+    }
+
+    pub fn rms_residual(&self) -> f64 {
+        if self.checks.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self.checks.iter().map(|c| c.residual * c.residual).sum();
+        (sum / self.checks.len() as f64).sqrt()
+    }
+
+    pub fn max_reference_residual(&self) -> Option<f64> {
+        self.checks
+            .iter()
+            .filter_map(|c| c.reference_residual)
+            .reduce(f64::max)
+    }
+
+    /// Formats a pair in the input or converted system of the report.
+    fn format_pair(&self, settings: &Settings, pair: [f64; 2], input: bool) -> String {
+        let geographic = input == (self.mode == ConversionMode::LatLontoUTM);
+        if geographic {
+            settings.format_geo(Some(pair))
+        } else {
+            settings.format_utm(Some(pair))
+        }
+    }
+
+    pub fn show(&self, ui: &mut egui::Ui, settings: &Settings) {
+        let metres = |value: f64| format!("{} m", settings.format_number(value, 4));
+        Grid::new("validation_summary")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("Checked");
+                ui.label(format!("{} points", self.checks.len()));
+                ui.end_row();
+                if !self.skipped.is_empty() {
+                    ui.label("Skipped");
+                    ui.label(format!(
+                        "{} points without a conversion",
+                        self.skipped.len()
+                    ));
+                    ui.end_row();
+                }
+                ui.label("Largest residual");
+                ui.label(metres(self.max_residual()));
+                ui.end_row();
+                ui.label("RMS residual");
+                ui.label(metres(self.rms_residual()));
+                ui.end_row();
+                if let Some(residual) = self.max_reference_residual() {
+                    ui.label("Largest reference difference");
+                    ui.label(metres(residual));
+                    ui.end_row();
+                }
+                ui.label("Over tolerance");
+                let flagged = self.flagged().count();
+                if flagged == 0 {
+                    ui.label("None");
+                } else {
+                    ui.colored_label(Color32::RED, format!("{} points", flagged));
+                }
+                ui.end_row();
+            });
+        ui.separator();
+        let row_height = ui.text_style_height(&egui::TextStyle::Body);
+        ScrollArea::vertical().max_height(240.0).show_rows(
+            ui,
+            row_height,
+            self.checks.len(),
+            |ui, rows| {
+                Grid::new("validation_checks")
+                    .striped(true)
+                    .num_columns(3)
+                    .show(ui, |ui| {
+                        ui.strong("Point");
+                        ui.strong("Residual");
+                        ui.strong("Reference");
+                        ui.end_row();
+                        for check in &self.checks[rows] {
+                            let text = |text: String| {
+                                if self.is_flagged(check) {
+                                    RichText::new(text).color(Color32::RED)
+                                } else {
+                                    RichText::new(text)
+                                }
+                            };
+                            ui.label(text((check.point + 1).to_string()));
+                            ui.label(text(metres(check.residual)));
+                            ui.label(text(
+                                check.reference_residual.map_or("-".to_string(), metres),
+                            ));
+                            ui.end_row();
+                        }
+                    });
+            },
+        );
+    }
+
+    /// Tab separated QA report: the summary, then one row per point.
+    pub fn write(&self, out: &mut impl Write, settings: &Settings) -> io::Result<()> {
+        let metres = |value: f64| settings.format_number(value, 4);
+        let (input, converted) = match self.mode {
+            ConversionMode::LatLontoUTM => (["Latitude", "Longitude"], ["Easting", "Northing"]),
+            ConversionMode::UTMtoLatLon => (["Easting", "Northing"], ["Latitude", "Longitude"]),
+        };
+        writeln!(out, "# Round-trip validation of {}", self.layer)?;
+        writeln!(out, "# Conversion\t{}", self.mode)?;
+        writeln!(out, "# Tolerance (m)\t{}", metres(self.tolerance))?;
+        writeln!(out, "# Checked points\t{}", self.checks.len())?;
+        writeln!(out, "# Skipped points\t{}", self.skipped.len())?;
+        writeln!(
+            out,
+            "# Largest residual (m)\t{}",
+            metres(self.max_residual())
+        )?;
+        writeln!(out, "# RMS residual (m)\t{}", metres(self.rms_residual()))?;
+        if let Some(residual) = self.max_reference_residual() {
+            writeln!(
+                out,
+                "# Largest reference difference (m)\t{}",
+                metres(residual)
+            )?;
+        }
+        writeln!(out, "# Over tolerance\t{}", self.flagged().count())?;
+        writeln!(
+            out,
+            "Id\t{0}\t{1}\t{2}\t{3}\tBack {0}\tBack {1}\tResidual (m)\tReference (m)\tStatus",
+            input[0], input[1], converted[0], converted[1]
+        )?;
+        for check in &self.checks {
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                check.point + 1,
+                self.format_pair(settings, check.input, true),
+                self.format_pair(settings, check.converted, false),
+                self.format_pair(settings, check.back, true),
+                metres(check.residual),
+                check.reference_residual.map_or(String::new(), metres),
+                if self.is_flagged(check) { "FAIL" } else { "OK" }
+            )?;
+        }
+        for (point, reason) in &self.skipped {
+            writeln!(out, "{}\t\t\t\t\t\t\t\t\tSKIPPED: {}", point + 1, reason)?;
+        }
+        Ok(())
+    }
+}
+
+/// Geodesic distance in metres between [lon, lat] positions.
+fn geodesic(a: [f64; 2], b: [f64; 2]) -> f64 {
+    inverse(a, b).map_or(f64::NAN, |(distance, _, _)| distance)
+}
+
+/// The numbers of each line of a pasted table, lines without numbers skipped.
+pub fn parse_table(input: &str) -> Vec<Vec<f64>> {
+    input
+        .lines()
+        .map(parse_numbers)
+        .filter(|numbers| !numbers.is_empty())
+        .collect()
+}
+
+pub struct ValidationTool {
+    pub window_open: bool,
+    /// Largest accepted residual, in metres
+    pub tolerance: f64,
+    /// Pasted table with the expected converted coordinates, by row
+    table: Vec<Vec<f64>>,
+    /// Columns of the table read as the reference, from 1
+    pub columns: [usize; 2],
+    /// Last report, with the layer id and history revision it was built for
+    report: Option<((u64, u64), Arc<Report>)>,
+}
+
+impl Default for ValidationTool {
+    fn default() -> Self {
+        ValidationTool {
+            window_open: false,
+            tolerance: 0.001,
+            table: vec![],
+            columns: [1, 2],
+            report: None,
+        }
+    }
+}
+
+impl ValidationTool {
+    /// The reference of each row, from the chosen columns of the table.
+    pub fn reference(&self) -> Vec<Option<[f64; 2]>> {
+        let [a, b] = self.columns.map(|column| column.saturating_sub(1));
+        self.table
+            .iter()
+            .map(|row| Some([*row.get(a)?, *row.get(b)?]))
+            .collect()
+    }
+
+    /// The report of `layer`, built again only when the layer, its history
+    /// revision or the settings of the tool changed.
+    pub fn report(&mut self, layer: &Layer, revision: u64) -> Arc<Report> {
+        let key = (layer.id(), revision);
+        match &self.report {
+            Some((cached, report)) if *cached == key && report.tolerance == self.tolerance => {
+                report.clone()
+            }
+            _ => {
+                let report = Arc::new(Report::new(layer, self.tolerance, &self.reference()));
+                self.report = Some((key, report.clone()));
+                report
+            }
+        }
+    }
+
+    /// Builds the report again on the next frame, after changes that leave the
+    /// history revision as it is.
+    pub fn invalidate(&mut self) {
+        self.report = None;
+    }
+
+    pub fn table_rows(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn set_table(&mut self, table: Vec<Vec<f64>>) {
+        self.table = table;
+        self.invalidate();
+    }
+
+    /// Picks the two columns of the table read as the reference.
+    pub fn show_columns(&mut self, ui: &mut egui::Ui) {
+        let width = self.table.iter().map(Vec::len).max().unwrap_or(0).max(2);
+        ui.label("Columns");
+        for column in 0..2 {
+            if ui
+                .add(DragValue::new(&mut self.columns[column]).range(1..=width))
+                .on_hover_text("Column of the reference table, from 1")
+                .changed()
+            {
+                self.invalidate();
+            }
+        }
+    }
+
+    pub fn show_tolerance(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Tolerance");
+            ui.add(
+                DragValue::new(&mut self.tolerance)
+                    .range(0.0..=1000.0)
+                    .speed(0.001)
+                    .max_decimals(4)
+                    .suffix(" m"),
+            );
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detection::Interpretation;
+    use crate::layer::LAYER_COLORS;
+
+    #[test]
+    fn test_report() {
+        let mut layer = Layer::new("Survey".to_string(), LAYER_COLORS[0]);
+        layer.raw_rows = vec![[41.65, -0.87, 0.0], [-33.9, 18.4, 0.0], [86.0, 0.0, 0.0]];
+        layer.set_interpretation(Interpretation::LatLon);
+        layer.convert().unwrap();
+        let [x, y] = layer.points[0].utm.unwrap();
+        // the reference of the second point is 5 m off
+        let [x2, y2] = layer.points[1].utm.unwrap();
+        let reference = [Some([x, y]), Some([x2 + 3.0, y2 + 4.0])];
+
+        let report = Report::new(&layer, 0.5, &reference);
+        assert_eq!(report.checks.len(), 2);
+        assert_eq!(report.skipped.len(), 1);
+        assert!(report.max_residual() < 0.5);
+        assert_eq!(report.checks[0].reference_residual, Some(0.0));
+        assert!((report.max_reference_residual().unwrap() - 5.0).abs() < 1e-6);
+        let flagged: Vec<usize> = report.flagged().map(|check| check.point).collect();
+        assert_eq!(flagged, vec![1]);
+
+        let mut out = vec![];
+        report.write(&mut out, &Settings::default()).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("# Over tolerance\t1\n"));
+        assert!(
+            text.lines()
+                .any(|line| line.starts_with("2\t") && line.ends_with("\tFAIL"))
+        );
+        assert!(
+            text.lines()
+                .any(|line| line.starts_with("3\t") && line.contains("SKIPPED"))
+        );
+    }
+
+    #[test]
+    fn test_reference_columns_and_cache() {
+        let mut layer = Layer::new("Survey".to_string(), LAYER_COLORS[0]);
+        layer.raw_rows = vec![[41.65, -0.87, 0.0], [-33.9, 18.4, 0.0]];
+        layer.set_interpretation(Interpretation::LatLon);
+        layer.convert().unwrap();
+        let [x, y] = layer.points[0].utm.unwrap();
+
+        // the input and the reference copied together, the second row short
+        let mut tool = ValidationTool::default();
+        tool.set_table(parse_table(&format!(
+            "Lat\tLon\tE\tN\n41.65\t-0.87\t{x}\t{y}\n-33.9\t18.4\n"
+        )));
+        assert_eq!(tool.table_rows(), 2);
+        tool.columns = [3, 4];
+        assert_eq!(tool.reference(), vec![Some([x, y]), None]);
+
+        let report = tool.report(&layer, 1);
+        assert_eq!(report.checks[0].reference_residual, Some(0.0));
+        assert_eq!(report.checks[1].reference_residual, None);
+        // the same layer and revision share the report until invalidated
+        assert!(Arc::ptr_eq(&report, &tool.report(&layer, 1)));
+        assert!(!Arc::ptr_eq(&report, &tool.report(&layer, 2)));
+        let report = tool.report(&layer, 2);
+        tool.tolerance = 1.0;
+        assert!(!Arc::ptr_eq(&report, &tool.report(&layer, 2)));
+        let report = tool.report(&layer, 2);
+        tool.invalidate();
+        assert!(!Arc::ptr_eq(&report, &tool.report(&layer, 2)));
+    }
+}